@@ -0,0 +1,70 @@
+//! Generate the table of the built-in level files: every pack manifest of `resources/packs`
+//! and the level files it lists, so adding a campaign only needs a new manifest.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PACKS_DIR: &str = "packs";
+
+fn main() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let packs_dir = resources.join(PACKS_DIR);
+    println!("cargo:rerun-if-changed={}", packs_dir.display());
+    let mut manifests = fs::read_dir(&packs_dir)
+        .unwrap_or_else(|err| panic!("{}: {}", packs_dir.display(), err))
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "toml"))
+        .collect::<Vec<_>>();
+    manifests.sort();
+    let mut files: Vec<String> = vec![];
+    for manifest in manifests.iter() {
+        let content = fs::read_to_string(manifest)
+            .unwrap_or_else(|err| panic!("{}: {}", manifest.display(), err));
+        let pack = content
+            .parse::<toml::Value>()
+            .unwrap_or_else(|err| panic!("{}: {}", manifest.display(), err));
+        files.push(format!(
+            "{}/{}",
+            PACKS_DIR,
+            manifest.file_name().unwrap().to_string_lossy()
+        ));
+        let levels = pack.get("levels").and_then(|levels| levels.as_array());
+        for level in levels.into_iter().flatten() {
+            if let Some(file) = level.get("file").and_then(|file| file.as_str()) {
+                let file = join(PACKS_DIR, file);
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+    }
+    let mut table = String::from("&[\n");
+    for file in files.iter() {
+        let path = resources.join(file);
+        println!("cargo:rerun-if-changed={}", path.display());
+        table.push_str(&format!(
+            "    ({:?}, include_str!({:?})),\n",
+            file,
+            path.to_string_lossy()
+        ));
+    }
+    table.push_str("]\n");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("builtin.rs");
+    fs::write(&out, table).unwrap_or_else(|err| panic!("{}: {}", out.display(), err));
+}
+
+/// Join a path relative to dir, resolving '.' and '..' components, as `assets::join` does.
+fn join(dir: &str, path: &str) -> String {
+    let mut components: Vec<&str> = vec![];
+    for component in dir.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
@@ -1,16 +1,23 @@
 use crate::{
-    ai_cache::AiStatesCache,
+    ai_cache::{AiStatesCache, DEFAULT_MAX_STATES},
     components::{Actuator, Cardinal, Movable, Player, Position},
+    game_core::Game,
+    game_state::Rules,
     gui::{draw_ui, MainMenuSelection},
-    map,
-    player::{try_actuate, try_move_player, try_teleport_player},
+    level, map,
+    pack::LevelPacks,
+    player::{try_actuate, try_move_player, try_teleport_player, PlayerInput},
+    solver::{self, Metric, Search},
     turn_history::{TurnState, TurnsHistory},
     RunState, TERM_WIDTH,
 };
 use bracket_lib::prelude::*;
 use legion::prelude::*;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::SeedableRng;
+use rand_pcg::Pcg64;
+use std::sync::mpsc;
+use std::thread;
 use std::time;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -26,17 +33,28 @@ pub enum AiSubAction {
     Actuate,
 }
 impl AiSubAction {
+    /// Input of the sub action, None for a teleport.
+    fn input(&self) -> Option<PlayerInput> {
+        match self {
+            AiSubAction::Move(direction) => Some(PlayerInput::Move(*direction)),
+            AiSubAction::MoveTo(_, _) => None,
+            AiSubAction::Actuate => Some(PlayerInput::Actuate),
+        }
+    }
     /// Play this SubAction in the World, and return if it was successful
     /// Successful meaning that the state of the world changed.
     fn play(&self, ecs: &mut World, rsrc: &mut Resources) -> bool {
-        let actions = match &self {
-            AiSubAction::Move(cardinal) => try_move_player(*cardinal, ecs, rsrc),
-            AiSubAction::MoveTo(x, y) => try_teleport_player(*x, *y, ecs, rsrc),
-            AiSubAction::Actuate => try_actuate(ecs, rsrc),
+        let (input, actions) = match &self {
+            AiSubAction::Move(cardinal) => (
+                Some(PlayerInput::Move(*cardinal)),
+                try_move_player(*cardinal, ecs, rsrc),
+            ),
+            AiSubAction::MoveTo(x, y) => (None, try_teleport_player(*x, *y, ecs, rsrc)),
+            AiSubAction::Actuate => (Some(PlayerInput::Actuate), try_actuate(ecs, rsrc)),
         };
         if actions.len() > 0 {
             let mut turn_history = rsrc.get_mut::<TurnsHistory>().unwrap();
-            turn_history.play_turn(ecs, actions);
+            turn_history.play_turn(ecs, input, actions);
             true
         } else {
             false
@@ -44,40 +62,97 @@ impl AiSubAction {
     }
 }
 
+/// Seed of the AI exploration when none is given.
+pub const DEFAULT_SEED: u64 = 0;
+/// Longest search of the Solve mode, it runs on its own thread while the window is refreshed.
+const SOLVE_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// How the AI plays a level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AiMode {
+    /// Randomized depth first search, counting the solutions
+    Explore,
+    /// Search the minimal solution for the metric, then play it
+    Solve(Metric),
+}
+impl AiMode {
+    fn next(&self) -> AiMode {
+        match self {
+            AiMode::Explore => AiMode::Solve(Metric::Moves),
+            AiMode::Solve(Metric::Moves) => AiMode::Solve(Metric::Pushes),
+            AiMode::Solve(Metric::Pushes) => AiMode::Solve(Metric::Energy),
+            AiMode::Solve(Metric::Energy) => AiMode::Explore,
+        }
+    }
+    fn name(&self) -> String {
+        match self {
+            AiMode::Explore => String::from("explore"),
+            AiMode::Solve(Metric::Energy) => String::from("least energy"),
+            AiMode::Solve(metric) => format!("fewest {}", metric.name()),
+        }
+    }
+}
+
 pub struct AI {
+    pub mode: AiMode,
+    /// Seed of the order the possible actions are explored in, the same seed explores the same way
+    pub seed: u64,
+    /// A named generator, its sequence for a seed does not change with the rand version
+    rng: Pcg64,
     pub show: bool,
     pub dead_ends: u64,
     pub solutions: u64,
     pub duplicates: u64,
     pub paused: bool,
     pub finished: bool,
+    /// The exploration stopped before the end as the cache of states is full
+    pub gave_up: bool,
     start_time: time::Instant,
-    searches: i32,
+    pub searches: i32,
     seen: AiStatesCache,
     history: AiHistory,
     tested_action: AiAction,
     sub_actions: Vec<AiSubAction>,
     sub_actions_success: bool,
+    /// Search of the Solve mode running on its own thread
+    solving: Option<mpsc::Receiver<Search>>,
+    /// Result of the search in Solve mode, or why the level could not be searched
+    search: Option<Result<Search, String>>,
+    /// Lowest energy of the solutions found, and the sub actions playing it
+    best: Option<(i32, Vec<AiSubAction>)>,
+    /// Sub actions of a solution being played, instead of searching
+    plan: Option<Vec<AiSubAction>>,
+    /// Directions walked by each teleport, indexed by turn, so solutions only hold moves
+    walks: Vec<Vec<Cardinal>>,
 }
 //fn a_star_search<T>(start: T, end: T, map: &dyn BaseMap) -> NavigationPath
 impl AI {
-    pub fn new() -> AI {
+    pub fn new(mode: AiMode, seed: u64) -> AI {
         AI {
+            mode,
+            seed,
+            rng: Pcg64::seed_from_u64(seed),
             show: true,
             dead_ends: 0,
             solutions: 0,
             duplicates: 0,
             paused: true,
             finished: false,
+            gave_up: false,
             start_time: time::Instant::now(),
             searches: 0,
-            seen: AiStatesCache::new(),
+            seen: AiStatesCache::new(Some(DEFAULT_MAX_STATES)),
             history: AiHistory {
                 possibilities: vec![],
             },
             tested_action: AiAction::ExitTo(0, 0),
             sub_actions: vec![],
             sub_actions_success: true,
+            solving: None,
+            search: None,
+            best: None,
+            plan: None,
+            walks: vec![],
         }
     }
     pub fn draw_state(&self, rsrc: &Resources, ctx: &mut BTerm) {
@@ -85,8 +160,47 @@ impl AI {
         ctx.print(20, 1, format!("Dead-ends : {}", self.dead_ends));
         ctx.print(20, 2, format!("Duplicates: {}", self.duplicates));
         ctx.print(20, 3, format!("Solutions : {}", self.solutions));
-        ctx.print(20, 4, format!("Min Energy: {}", "None"));
-        ctx.print(1, 28, format!("Cache Size: {}", self.seen.get_size()));
+        ctx.print(
+            20,
+            4,
+            format!(
+                "Min Energy: {}",
+                self.best
+                    .as_ref()
+                    .map_or(String::from("None"), |(energy, _)| energy.to_string())
+            ),
+        );
+        ctx.print(20, 5, format!("Seed      : {} (+/-)", self.seed));
+        ctx.print(1, 27, format!("Mode: {} (M)", self.mode.name()));
+        match &self.search {
+            Some(Ok(search)) => ctx.print(
+                1,
+                28,
+                match &search.solution {
+                    Some(solution) => format!(
+                        "Solution: {} moves, {} pushes, {} energy{}",
+                        solution.steps,
+                        solution.pushes,
+                        solution.energy,
+                        if search.complete {
+                            " (minimal)"
+                        } else {
+                            " (may not be minimal)"
+                        }
+                    ),
+                    None if search.complete => String::from("No solution"),
+                    None => format!("Gave up after {} states", search.explored),
+                },
+            ),
+            Some(Err(err)) => ctx.print(1, 28, format!("Cannot solve level: {}", err)),
+            None if self.solving.is_some() => ctx.print(1, 28, "Searching the solution..."),
+            None if self.gave_up => ctx.print(
+                1,
+                28,
+                format!("Gave up, cache full at {} states", self.seen.get_size()),
+            ),
+            None => ctx.print(1, 28, format!("Cache Size: {}", self.seen.get_size())),
+        }
         ctx.print(
             1,
             29,
@@ -96,7 +210,11 @@ impl AI {
             ),
         );
         if self.finished {
-            let txt = "Solved !";
+            let txt = if self.gave_up {
+                "Gave up !"
+            } else {
+                "Solved !"
+            };
             let start_x = (TERM_WIDTH - (txt.len() as i32 + 4)) / 2;
             let start_y = 6;
             ctx.draw_box(
@@ -111,6 +229,14 @@ impl AI {
         } else if self.paused {
             ctx.print_color_centered(7, RGB::named(YELLOW), RGB::named(BLACK), "Paused!");
         }
+        if self.paused && self.best.is_some() {
+            ctx.print_color_centered(
+                8,
+                RGB::named(YELLOW),
+                RGB::named(BLACK),
+                "P to play the best solution",
+            );
+        }
     }
     pub fn play_next_turn(
         &mut self,
@@ -119,17 +245,39 @@ impl AI {
         ctx: &mut BTerm,
     ) -> RunState {
         if !self.paused {
+            self.play_turn(ecs, rsrc);
+            if self.finished {
+                return RunState::GameDraw;
+            }
+        }
+        self.get_next_runstate(rsrc, ctx)
+    }
+    /// Play the next turn of the AI in the World, the systems are then run by the caller.
+    fn play_turn(&mut self, ecs: &mut World, rsrc: &mut Resources) {
+        if self.plan.is_some() {
+            self.play_plan(ecs, rsrc);
+        } else if let AiMode::Solve(metric) = self.mode {
+            self.solve(metric, rsrc);
+        } else {
             if self.sub_actions.is_empty() {
                 let mut turn_history = rsrc.get_mut::<TurnsHistory>().unwrap();
                 let cur_step = turn_history.steps;
+                let map = rsrc.get::<map::Map>().unwrap();
                 self.searches += 1;
-                if self.seen.has_seen(ecs) {
+                if self.seen.has_seen(ecs, &map, cur_step) {
                     self.sub_actions_success = false;
                     //println!("  DUP");
                     self.duplicates += 1;
                 }
+                if self.seen.is_full() {
+                    // New states are not remembered anymore, the search could loop forever.
+                    turn_history.undo(cur_step, ecs);
+                    self.gave_up = true;
+                    self.paused = true;
+                    self.finished = true;
+                    return;
+                }
                 if self.sub_actions_success {
-                    let map = rsrc.get::<map::Map>().unwrap();
                     if map.is_impossible(&ecs) {
                         //self.paused = true;
                         self.dead_ends += 1;
@@ -139,7 +287,7 @@ impl AI {
                 }
                 if self.sub_actions_success {
                     let mut possibilities = self.find_possible_actions(ecs, rsrc);
-                    possibilities.shuffle(&mut thread_rng());
+                    possibilities.shuffle(&mut self.rng);
                     if possibilities.len() > 0 {
                         self.history.possibilities.push((cur_step, possibilities));
                     //println!("UPD {:?}", self.history.possibilities);
@@ -159,7 +307,7 @@ impl AI {
                         turn_history.undo(cur_step, ecs);
                         self.paused = true;
                         self.finished = true;
-                        return RunState::GameDraw;
+                        return;
                     } else {
                         let undo_steps = cur_step - self.history.possibilities.last().unwrap().0;
                         turn_history.undo(undo_steps, ecs);
@@ -188,6 +336,9 @@ impl AI {
             }
             if !self.sub_actions.is_empty() {
                 let action = self.sub_actions.remove(0);
+                if let AiSubAction::MoveTo(x, y) = action {
+                    self.record_walk(ecs, rsrc, (x, y));
+                }
                 self.sub_actions_success = true;
                 action.play(ecs, rsrc);
                 if !self.sub_actions_success {
@@ -197,11 +348,195 @@ impl AI {
                 //println!("    DO {:?}", action)
             }
         }
-        self.get_next_runstate(ctx)
     }
-    fn get_next_runstate(&mut self, ctx: &mut BTerm) -> RunState {
+    /// Explore the level of the game until every state has been searched or the timeout,
+    /// without any window.
+    pub fn explore(&mut self, game: &mut Game, timeout: Option<time::Duration>) {
+        self.paused = false;
+        self.start_time = time::Instant::now();
+        while !self.finished && timeout.map_or(true, |timeout| self.elapsed() < timeout) {
+            self.play_turn(&mut game.ecs, &mut game.rsrc);
+            // The game runs the systems once for the turn and once more before drawing.
+            game.run_systems();
+            game.run_systems();
+            if !self.finished {
+                self.end_turn(&game.rsrc);
+            }
+        }
+    }
+    /// Time since the AI started, or was last unpaused.
+    pub fn elapsed(&self) -> time::Duration {
+        self.start_time.elapsed()
+    }
+    /// States remembered by the exploration.
+    pub fn cache_size(&self) -> usize {
+        self.seen.get_size()
+    }
+    pub fn set_max_states(&mut self, max_states: usize) {
+        self.seen = AiStatesCache::new(Some(max_states));
+    }
+    /// Steps, energy and moves of the best solution found.
+    pub fn best_solution(&self) -> Option<(i32, i32, String)> {
+        self.best.as_ref().map(|(energy, sub_actions)| {
+            let moves = sub_actions
+                .iter()
+                .filter_map(AiSubAction::input)
+                .map(|input| input.to_char())
+                .collect();
+            (sub_actions.len() as i32, *energy, moves)
+        })
+    }
+    /// Search the solution of the level on a thread, and plan to play it once found.
+    /// Each call checks whether the search is over, starting it the first time.
+    fn solve(&mut self, metric: Metric, rsrc: &Resources) {
+        let solving = match self.solving.take() {
+            Some(solving) => Ok(solving),
+            None => AI::start_search(metric, rsrc),
+        };
+        let search = solving.and_then(|solving| match solving.try_recv() {
+            Ok(search) => Ok(Some(search)),
+            Err(mpsc::TryRecvError::Empty) => {
+                self.solving = Some(solving);
+                Ok(None)
+            }
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Err(String::from("The search stopped without a result"))
+            }
+        });
+        let search = match search {
+            Ok(Some(search)) => Ok(search),
+            Ok(None) => return,
+            Err(err) => Err(err),
+        };
+        match search
+            .as_ref()
+            .ok()
+            .and_then(|search| search.solution.as_ref())
+        {
+            Some(solution) => {
+                let sub_actions = solution
+                    .inputs
+                    .iter()
+                    .map(|input| match input {
+                        PlayerInput::Move(direction) => AiSubAction::Move(*direction),
+                        PlayerInput::Actuate => AiSubAction::Actuate,
+                    })
+                    .collect::<Vec<_>>();
+                self.best = Some((solution.energy, sub_actions.clone()));
+                self.plan = Some(sub_actions);
+            }
+            None => {
+                self.paused = true;
+                self.finished = true;
+            }
+        }
+        if let Ok(search) = search.as_ref() {
+            self.searches = search.explored as i32;
+        }
+        self.search = Some(search);
+    }
+    /// Play the next sub action of the plan, until it is done.
+    fn play_plan(&mut self, ecs: &mut World, rsrc: &mut Resources) {
+        let plan = self.plan.as_mut().unwrap();
+        if plan.is_empty() {
+            self.plan = None;
+            self.paused = true;
+            self.finished = true;
+        } else {
+            plan.remove(0).play(ecs, rsrc);
+        }
+    }
+    /// Keep the solution just found if it uses less energy than the best one.
+    fn record_solution(&mut self, rsrc: &Resources) {
+        let history = rsrc.get::<TurnsHistory>().unwrap();
+        if self
+            .best
+            .as_ref()
+            .map_or(false, |&(energy, _)| energy <= history.energy_used)
+        {
+            return;
+        }
+        let sub_actions = history
+            .inputs
+            .iter()
+            .enumerate()
+            .flat_map(|(turn, input)| match input {
+                Some(PlayerInput::Move(direction)) => vec![AiSubAction::Move(*direction)],
+                Some(PlayerInput::Actuate) => vec![AiSubAction::Actuate],
+                // Teleports are the only turns that are not player inputs
+                None => self.walks[turn]
+                    .iter()
+                    .map(|&direction| AiSubAction::Move(direction))
+                    .collect(),
+            })
+            .collect();
+        self.best = Some((history.energy_used, sub_actions));
+    }
+    /// Remember the directions walking to the tile the player is about to teleport to.
+    fn record_walk(&mut self, ecs: &World, rsrc: &Resources, to: (i32, i32)) {
+        let turn = rsrc.get::<TurnsHistory>().unwrap().inputs.len();
+        let map = rsrc.get::<map::Map>().unwrap();
+        let walk = <(Read<Position>,)>::query()
+            .filter(tag::<Player>())
+            .iter(ecs)
+            .next()
+            .and_then(|(pos,)| map.try_go_to((pos.x, pos.y), to))
+            .unwrap_or_default();
+        self.walks.truncate(turn);
+        self.walks.resize(turn + 1, vec![]);
+        self.walks[turn] = walk;
+    }
+    /// Start solving the current level from its file on a thread, giving up after SOLVE_TIMEOUT.
+    fn start_search(metric: Metric, rsrc: &Resources) -> Result<mpsc::Receiver<Search>, String> {
+        let level = rsrc.get::<map::Map>().unwrap().level;
+        let file = level.and_then(|level| rsrc.get::<LevelPacks>().unwrap().level_path(level));
+        let (rules, start) = file
+            .ok_or_else(|| String::from("Level not found in its pack"))
+            .and_then(|file| level::read_level(&file).map_err(|err| err.message()))
+            .and_then(|description| Rules::new(&description))?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let search = solver::solve(
+                &rules,
+                &start,
+                metric,
+                DEFAULT_MAX_STATES,
+                Some(SOLVE_TIMEOUT),
+            );
+            // Nobody waits for the result anymore when the AI was restarted meanwhile.
+            sender.send(search).ok();
+        });
+        Ok(receiver)
+    }
+    fn get_next_runstate(&mut self, rsrc: &Resources, ctx: &mut BTerm) -> RunState {
         if let Some(key) = ctx.key {
             match key {
+                VirtualKeyCode::P if self.paused && self.best.is_some() => {
+                    // Start the level again to play the best solution
+                    if let Some(level) = rsrc.get::<map::Map>().unwrap().level {
+                        self.plan = self.best.as_ref().map(|(_, plan)| plan.clone());
+                        self.paused = false;
+                        self.finished = false;
+                        return RunState::LoadLevel(level);
+                    }
+                }
+                VirtualKeyCode::M => {
+                    if let Some(runstate) = self.restart(self.mode.next(), self.seed, rsrc) {
+                        return runstate;
+                    }
+                }
+                VirtualKeyCode::Add | VirtualKeyCode::Equals => {
+                    if let Some(runstate) = self.restart(self.mode, self.seed.wrapping_add(1), rsrc)
+                    {
+                        return runstate;
+                    }
+                }
+                VirtualKeyCode::Subtract | VirtualKeyCode::Minus => {
+                    if let Some(runstate) = self.restart(self.mode, self.seed.wrapping_sub(1), rsrc)
+                    {
+                        return runstate;
+                    }
+                }
                 VirtualKeyCode::Space => {
                     self.paused = !self.paused;
                     if !self.paused {
@@ -226,14 +561,30 @@ impl AI {
             RunState::GameTurn
         }
     }
+    /// Start the level again with a new AI in the mode and seed.
+    fn restart(&mut self, mode: AiMode, seed: u64, rsrc: &Resources) -> Option<RunState> {
+        let show = self.show;
+        *self = AI::new(mode, seed);
+        self.show = show;
+        rsrc.get::<map::Map>()
+            .unwrap()
+            .level
+            .map(RunState::LoadLevel)
+    }
     pub fn end_turn(&mut self, rsrc: &Resources) -> RunState {
         if self.paused {
             return RunState::GameAwaitingInput;
         }
         if self.finished {
             let level = rsrc.get::<map::Map>().unwrap().level;
-            *self = AI::new();
-            return RunState::LoadLevel(level + 1);
+            let packs = rsrc.get::<LevelPacks>().unwrap();
+            *self = AI::new(self.mode, self.seed);
+            return match level.and_then(|level| packs.next(level)) {
+                Some(level) => RunState::LoadLevel(level),
+                None => RunState::MainMenu {
+                    menu_selection: MainMenuSelection::NewAiGame,
+                },
+            };
         }
         let curstate = rsrc.get::<TurnsHistory>().unwrap().state;
         match curstate {
@@ -243,8 +594,10 @@ impl AI {
                 //println!("  DEAD");
                 RunState::GameAwaitingInput
             }
+            TurnState::PlayerAtExit if self.plan.is_some() => RunState::GameAwaitingInput,
             TurnState::PlayerAtExit => {
                 self.solutions += 1;
+                self.record_solution(rsrc);
                 self.sub_actions_success = false;
                 //println!("  EXIT");
                 RunState::GameAwaitingInput
@@ -325,3 +678,41 @@ impl AI {
 struct AiHistory {
     possibilities: Vec<(i32, Vec<(AiAction, Vec<AiSubAction>)>)>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::read_level;
+
+    /// Actions tested by an exploration of ai_test_6 in its first turns.
+    fn first_actions(seed: u64) -> Vec<AiAction> {
+        let packs = LevelPacks::load().unwrap();
+        let file = packs
+            .find("ai_tests/ai_test_6")
+            .and_then(|level| packs.level_path(level))
+            .unwrap();
+        let description = read_level(&file).unwrap_or_else(|err| panic!("{}", err));
+        let mut game = Game::with_rules(&description);
+        let mut ai = AI::new(AiMode::Explore, seed);
+        ai.paused = false;
+        let mut actions = vec![];
+        for _ in 0..100 {
+            ai.play_turn(&mut game.ecs, &mut game.rsrc);
+            if ai.finished {
+                break;
+            }
+            game.run_systems();
+            game.run_systems();
+            ai.end_turn(&game.rsrc);
+            actions.push(ai.tested_action.clone());
+        }
+        actions
+    }
+
+    #[test]
+    fn same_seed_explores_the_same_way() {
+        let actions = first_actions(42);
+        assert!(!actions.is_empty());
+        assert_eq!(actions, first_actions(42));
+    }
+}
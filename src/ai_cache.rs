@@ -1,57 +1,148 @@
-use crate::components::{Actuator, Movable, Player, Position};
+//! States already explored by the AI, identified by a Zobrist hash of the level state:
+//! one random key per (feature, tile) xored together, so the order of the entities does not matter
+//! and two identical blocks swapped give the same state.
+//! Latched plates and the turns since a timer was triggered are part of the state too.
+use crate::components::{
+    Activable, ActivationBehaviour, Actuator, Block, Cardinal, Laser, Player, Position,
+};
+use crate::map::Map;
 use legion::prelude::*;
+use std::collections::HashSet;
+
+/// States kept by default, a few tens of megabytes of hashes.
+pub const DEFAULT_MAX_STATES: usize = 1 << 22;
+
+/// What can be on a tile and is part of the state.
+#[derive(Clone, Copy)]
+enum Feature {
+    Player,
+    Block,
+    Laser(Cardinal),
+    Actuator(u8),
+    Latched,
+    /// Timed activable still active, its key is mixed with the turns since it was triggered
+    Timer,
+}
+impl Feature {
+    fn index(&self) -> usize {
+        match self {
+            Feature::Player => 0,
+            Feature::Block => 1,
+            Feature::Laser(Cardinal::N) => 2,
+            Feature::Laser(Cardinal::S) => 3,
+            Feature::Laser(Cardinal::E) => 4,
+            Feature::Laser(_) => 5,
+            Feature::Actuator(state) => 6 + *state as usize % 2,
+            Feature::Latched => 8,
+            Feature::Timer => 9,
+        }
+    }
+}
+const FEATURES: usize = 10;
+
+/// splitmix64 finalizer, spreading the bits of z.
+fn mix(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 pub struct AiStatesCache {
-    movables: Vec<Entity>,
-    actuators: Vec<Entity>,
-    seen: Vec<(Vec<Position>, Vec<(Position, Actuator)>)>,
+    keys: Vec<u64>,
+    tiles: usize,
+    seen: HashSet<u64>,
+    /// Number of states after which no new state is remembered, None to keep them all
+    max_size: Option<usize>,
 }
 
 impl AiStatesCache {
-    pub fn new() -> AiStatesCache {
+    pub fn new(max_size: Option<usize>) -> AiStatesCache {
         AiStatesCache {
-            movables: vec![],
-            actuators: vec![],
-            seen: vec![],
+            keys: vec![],
+            tiles: 0,
+            seen: HashSet::new(),
+            max_size,
         }
     }
-    pub fn init(&mut self, ecs: &World) {
-        let query = <(Read<Position>,)>::query().filter(tag::<Movable>());
-        for (entity, (_pos,)) in query.iter_entities(&ecs) {
-            self.movables.push(entity);
+    /// Random keys for every feature on every tile of the map.
+    fn init(&mut self, map: &Map) {
+        self.tiles = (map.width * map.height) as usize;
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        self.keys = (0..FEATURES * self.tiles)
+            .map(|_| {
+                // splitmix64
+                seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                mix(seed)
+            })
+            .collect();
+    }
+    fn key(&self, feature: Feature, pos: &Position, map: &Map) -> u64 {
+        let tile = (pos.y * map.width + pos.x) as usize;
+        self.keys[feature.index() * self.tiles + tile]
+    }
+    /// Zobrist hash of the region of the player, movables, actuators and activables at step.
+    fn hash(&mut self, ecs: &World, map: &Map, step: i32) -> u64 {
+        if self.keys.is_empty() {
+            self.init(map);
         }
-        // Also add the player, as same state but reached from different player position
-        // is different
-        let query = <(Read<Position>,)>::query().filter(tag::<Player>());
-        for (entity, (_pos,)) in query.iter_entities(&ecs) {
-            self.movables.push(entity);
+        let mut hash = 0;
+        // The AI teleports the player anywhere it can walk to, so only the region it is in matters.
+        for (pos,) in <(Read<Position>,)>::query()
+            .filter(tag::<Player>())
+            .iter(ecs)
+        {
+            let region = map.region_min_idx((pos.x, pos.y)) as i32;
+            let region = Position {
+                x: region % map.width,
+                y: region / map.width,
+            };
+            hash ^= self.key(Feature::Player, &region, map);
         }
-        let query = <(Read<Position>,)>::query().filter(component::<Actuator>());
-        for (entity, (_pos,)) in query.iter_entities(&ecs) {
-            self.actuators.push(entity);
+        for (pos,) in <(Read<Position>,)>::query()
+            .filter(tag::<Block>())
+            .iter(ecs)
+        {
+            hash ^= self.key(Feature::Block, &pos, map);
         }
-    }
-    pub fn has_seen(&mut self, ecs: &World) -> bool {
-        if self.seen.is_empty() {
-            self.init(ecs);
+        for (laser, pos) in <(Tagged<Laser>, Read<Position>)>::query().iter(ecs) {
+            hash ^= self.key(Feature::Laser(laser.direction), &pos, map);
         }
-        let mut m = vec![];
-        for &entity in self.movables.iter() {
-            m.push(*(ecs.get_component::<Position>(entity).unwrap()));
+        for (pos, actuator) in <(Read<Position>, Read<Actuator>)>::query().iter(ecs) {
+            hash ^= self.key(Feature::Actuator(actuator.state), &pos, map);
         }
-        let mut a = vec![];
-        for &entity in self.actuators.iter() {
-            let pos = ecs.get_component::<Position>(entity).unwrap();
-            let act = ecs.get_component::<Actuator>(entity).unwrap();
-            a.push((*pos, *act));
+        for (activable, pos) in <(Read<Activable>, Read<Position>)>::query().iter(ecs) {
+            match activable.behaviour {
+                ActivationBehaviour::Momentary => {}
+                ActivationBehaviour::Latching => {
+                    if activable.latched {
+                        hash ^= self.key(Feature::Latched, &pos, map);
+                    }
+                }
+                ActivationBehaviour::Timed(turns) => {
+                    let since = activable.last_triggered.map(|last| step - last);
+                    if let Some(since) = since.filter(|&since| since <= turns) {
+                        hash ^= mix(self.key(Feature::Timer, &pos, map) ^ since as u64);
+                    }
+                }
+            }
         }
-        let entry = (m, a);
-        let found = self.seen.contains(&entry);
-        if found {
-            true
-        } else {
-            self.seen.push(entry);
-            false
+        hash
+    }
+    /// True if the state at step has already been seen, else remember it
+    /// unless the cache is full.
+    pub fn has_seen(&mut self, ecs: &World, map: &Map, step: i32) -> bool {
+        let hash = self.hash(ecs, map, step);
+        if self.seen.contains(&hash) {
+            return true;
+        }
+        if !self.is_full() {
+            self.seen.insert(hash);
         }
+        false
+    }
+    /// True once max_size states are remembered, a search can then loop forever.
+    pub fn is_full(&self) -> bool {
+        self.max_size.map_or(false, |max| self.seen.len() >= max)
     }
     pub fn get_size(&self) -> usize {
         self.seen.len()
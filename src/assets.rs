@@ -0,0 +1,91 @@
+//! Level files, compiled in the binary so the game runs from any directory.
+//! Paths are relative to the resources directory, using '/' as separator.
+//! Directories listed in the GRIPHUS_LEVELS environment variable are searched first,
+//! so user-supplied files override or extend the built-in ones.
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub const LEVELS_PATH_ENV: &str = "GRIPHUS_LEVELS";
+pub const PACKS_DIR: &str = "packs";
+
+/// Manifests of `resources/packs` and the level files they list, generated by build.rs.
+const BUILTIN: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/builtin.rs"));
+
+/// Directory where the game writes the player data, replays and progress.
+pub fn user_data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("griphus"))
+}
+
+/// Directories searched before the built-in files.
+pub fn search_path() -> Vec<PathBuf> {
+    env::var_os(LEVELS_PATH_ENV)
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default()
+}
+
+/// Content of the file at path, from the search path or the built-in files.
+pub fn read(path: &str) -> io::Result<String> {
+    for dir in search_path().iter() {
+        let file = dir.join(path);
+        if file.is_file() {
+            return fs::read_to_string(file);
+        }
+    }
+    BUILTIN
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, content)| String::from(*content))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} not found in built-in levels nor in {}",
+                    path, LEVELS_PATH_ENV
+                ),
+            )
+        })
+}
+
+/// Paths of all the pack manifests, sorted.
+pub fn list_packs() -> Vec<String> {
+    let mut packs = BUILTIN
+        .iter()
+        .map(|(name, _)| String::from(*name))
+        .filter(|name| name.starts_with(PACKS_DIR) && name.ends_with(".toml"))
+        .collect::<Vec<_>>();
+    for dir in search_path().iter() {
+        if let Ok(entries) = fs::read_dir(dir.join(PACKS_DIR)) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let pack = format!("{}/{}", PACKS_DIR, name);
+                if name.ends_with(".toml") && !packs.contains(&pack) {
+                    packs.push(pack);
+                }
+            }
+        }
+    }
+    packs.sort();
+    packs
+}
+
+/// Join a path relative to dir, resolving '.' and '..' components.
+pub fn join(dir: &str, path: &str) -> String {
+    let mut components: Vec<&str> = vec![];
+    for component in dir.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+/// Directory part of a path.
+pub fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |pos| &path[..pos])
+}
@@ -0,0 +1,275 @@
+//! Checks of a level for problems making it unsolvable, run by the `check` subcommand.
+//! They are necessary conditions only, a level passing them can still be impossible.
+//! Checks that can also flag solvable levels are reported as warnings.
+use crate::components::{Cardinal, Logic};
+use crate::game_core::Game;
+use crate::legend::Activator;
+use crate::level::{LevelDescription, LevelEntity};
+use crate::map::{Map, TileType};
+use crate::systems::build_laser_systems;
+
+/// Receptors are only checked up to this many reflectors, as every combination of orientations
+/// is tried.
+const MAX_REFLECTORS: usize = 12;
+
+const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+#[derive(Default)]
+pub struct CheckReport {
+    /// Problems making the level unsolvable
+    pub problems: Vec<String>,
+    /// Possible problems, the level may still be solvable
+    pub warnings: Vec<String>,
+}
+
+/// Problems and warnings found in the level, both empty when none.
+pub fn check_level(description: &LevelDescription) -> CheckReport {
+    let mut report = CheckReport::default();
+    let problems = &mut report.problems;
+    let game = Game::new(description, build_laser_systems());
+    let map = game.rsrc.get::<Map>().unwrap();
+    let player = positions(description, |entity| entity == LevelEntity::Player)[0];
+    if map.is_lasered(player.0, player.1) {
+        problems.push(format!(
+            "Player starts in a laser beam at ({},{})",
+            player.0, player.1
+        ));
+    }
+    let movables = positions(description, is_movable);
+    let reaches_movable = movables.iter().any(|&(x, y)| {
+        DIRECTIONS
+            .iter()
+            .any(|&(dx, dy)| map.can_go_to(player, (x + dx, y + dy)))
+    });
+    if !movables.is_empty() && !reaches_movable {
+        problems.push(String::from(
+            "Player cannot reach any tile next to a movable",
+        ));
+    }
+    let reaches_exit = walk_reachable(description, player)
+        .iter()
+        .zip(description.tiles.iter())
+        .any(|(&walkable, &tile)| walkable && tile == TileType::Exit);
+    if !reaches_exit {
+        problems.push(String::from("Player cannot reach the exit"));
+    }
+    let pushable = movables
+        .iter()
+        .map(|&movable| push_reachable(description, movable))
+        .collect::<Vec<_>>();
+    let mut needed = vec![];
+    for door in description.doors.iter() {
+        needed_activators(&door.logic, false, &mut needed);
+    }
+    for (x, y) in positions(description, |entity| entity == LevelEntity::WeightPlate) {
+        if needed.contains(&Activator::Plate(x, y))
+            && !pushable.iter().any(|tiles| tiles[idx(description, x, y)])
+        {
+            problems.push(format!("No movable can be pushed on plate ({},{})", x, y));
+        }
+    }
+    let reflectors = positions(description, |entity| match entity {
+        LevelEntity::Reflector(_) => true,
+        _ => false,
+    });
+    let receptors = positions(description, |entity| entity == LevelEntity::LaserReceptor);
+    if reflectors.len() > MAX_REFLECTORS && !receptors.is_empty() {
+        report.warnings.push(format!(
+            "Receptors not checked, the level has more than {} reflectors",
+            MAX_REFLECTORS
+        ));
+    } else {
+        // Lasers and reflectors can be pushed elsewhere, so a beam may still reach the receptor.
+        for (x, y) in unreachable_receptors(description) {
+            report.warnings.push(format!(
+                "No laser beam reaches receptor ({},{}) from the start position of the lasers \
+                 and reflectors, whatever their orientation",
+                x, y
+            ));
+        }
+    }
+    report
+}
+
+/// Activators appearing in the logic outside of any not(..), that may need to be active.
+fn needed_activators(logic: &Logic<Activator>, negated: bool, needed: &mut Vec<Activator>) {
+    match logic {
+        Logic::Is(activator) => {
+            if !negated {
+                needed.push(*activator);
+            }
+        }
+        Logic::All(logics) | Logic::Any(logics) | Logic::One(logics) => {
+            for logic in logics.iter() {
+                needed_activators(logic, negated, needed);
+            }
+        }
+        Logic::Not(logic) => needed_activators(logic, !negated, needed),
+    }
+}
+
+fn is_movable(entity: LevelEntity) -> bool {
+    match entity {
+        LevelEntity::Block | LevelEntity::Laser(_) | LevelEntity::Reflector(_) => true,
+        _ => false,
+    }
+}
+
+fn positions<F: Fn(LevelEntity) -> bool>(description: &LevelDescription, f: F) -> Vec<(i32, i32)> {
+    description
+        .entities
+        .iter()
+        .filter(|&&(_, _, entity)| f(entity))
+        .map(|&(x, y, _)| (x, y))
+        .collect()
+}
+
+fn idx(description: &LevelDescription, x: i32, y: i32) -> usize {
+    (y * description.width + x) as usize
+}
+
+/// Tiles that never let a movable nor the player through: walls and receptors.
+/// Doors are ignored as they may open.
+fn is_static(description: &LevelDescription, x: i32, y: i32) -> bool {
+    if x < 0 || y < 0 || x >= description.width || y >= description.height {
+        return true;
+    }
+    description.tiles[idx(description, x, y)] == TileType::Wall
+        || description
+            .entities
+            .iter()
+            .any(|&(ex, ey, entity)| (ex, ey) == (x, y) && entity == LevelEntity::LaserReceptor)
+}
+
+/// Tiles the player can walk to from start, through the movables and doors as they can be
+/// pushed away or opened.
+fn walk_reachable(description: &LevelDescription, start: (i32, i32)) -> Vec<bool> {
+    let mut reached = vec![false; description.tiles.len()];
+    let mut stack = vec![start];
+    while let Some((x, y)) = stack.pop() {
+        let i = idx(description, x, y);
+        if reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        for &(dx, dy) in DIRECTIONS.iter() {
+            if !is_static(description, x + dx, y + dy) {
+                stack.push((x + dx, y + dy));
+            }
+        }
+    }
+    reached
+}
+
+/// Tiles a movable can be pushed to from start, ignoring the other movables.
+/// A push needs the player standing on the tile behind the movable.
+fn push_reachable(description: &LevelDescription, start: (i32, i32)) -> Vec<bool> {
+    let mut reached = vec![false; description.tiles.len()];
+    let mut stack = vec![start];
+    while let Some((x, y)) = stack.pop() {
+        let i = idx(description, x, y);
+        if reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        for &(dx, dy) in DIRECTIONS.iter() {
+            if !is_static(description, x + dx, y + dy) && !is_static(description, x - dx, y - dy) {
+                stack.push((x + dx, y + dy));
+            }
+        }
+    }
+    reached
+}
+
+/// Receptors not lasered in any orientation of the reflectors, movables staying at their start position.
+fn unreachable_receptors(description: &LevelDescription) -> Vec<(i32, i32)> {
+    let mut receptors = positions(description, |entity| entity == LevelEntity::LaserReceptor);
+    let reflectors = description
+        .entities
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(_, _, entity))| match entity {
+            LevelEntity::Reflector(_) => true,
+            _ => false,
+        })
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    for combination in 0..(1 << reflectors.len()) {
+        if receptors.is_empty() {
+            break;
+        }
+        let mut entities = description.entities.clone();
+        for (bit, &i) in reflectors.iter().enumerate() {
+            if combination & (1 << bit) != 0 {
+                if let (x, y, LevelEntity::Reflector(orientation)) = entities[i] {
+                    let flipped = if orientation == Cardinal::NE {
+                        Cardinal::NW
+                    } else {
+                        Cardinal::NE
+                    };
+                    entities[i] = (x, y, LevelEntity::Reflector(flipped));
+                }
+            }
+        }
+        let variant = LevelDescription {
+            info: description.info.clone(),
+            width: description.width,
+            height: description.height,
+            tiles: description.tiles.clone(),
+            entities,
+            doors: description.doors.clone(),
+            behaviours: description.behaviours.clone(),
+            hash: description.hash,
+        };
+        let game = Game::new(&variant, build_laser_systems());
+        let map = game.rsrc.get::<Map>().unwrap();
+        receptors.retain(|&(x, y)| !map.is_lasered(x, y));
+    }
+    receptors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::parse_level;
+
+    fn check(content: &str) -> CheckReport {
+        let description = parse_level("check", content).unwrap_or_else(|err| panic!("{}", err));
+        check_level(&description)
+    }
+
+    #[test]
+    fn walled_in_player_is_reported() {
+        let report = check(
+            "\
+#####E#
+#@.#..#
+#..#b.#
+#..#x.#
+#######
+",
+        );
+        assert_eq!(
+            report.problems,
+            vec![
+                "Player cannot reach any tile next to a movable",
+                "Player cannot reach the exit",
+            ]
+        );
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn solvable_level_passes() {
+        let report = check(
+            "\
+######
+#@b.x#
+#....E
+######
+",
+        );
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+}
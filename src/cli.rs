@@ -0,0 +1,544 @@
+//! Command line subcommands, running without opening a window.
+use crate::ai::{AiMode, AI, DEFAULT_SEED};
+use crate::ai_cache::DEFAULT_MAX_STATES;
+use crate::assets::{self, PACKS_DIR};
+use crate::check::check_level;
+use crate::game_core::{check_parity, verify, Game};
+use crate::game_state::Rules;
+use crate::level::{
+    load_level_from_file, read_level, LevelDescription, LevelError, LevelErrorKind,
+};
+use crate::pack::{LevelPack, LevelPacks, PackLevel};
+use crate::player::parse_inputs;
+use crate::sokoban;
+use crate::solver::{pareto_front, solve, Metric};
+use crate::turn_history::TurnState;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage:
+    rs-griphus                              Start the game
+    rs-griphus import <collection.xsb> <dir> Convert Sokoban levels to a Griphus pack in dir
+    rs-griphus check [<file|pack>...]       Report problems in level files, pack manifests or
+                                            packs by name, every pack when none is given
+    rs-griphus verify <level> <moves>       Play moves like LLURRA on a level file or pack/id
+    rs-griphus verify <solutions>           Check every <pack>/<id>.txt move file in solutions
+                                            solves its level
+    rs-griphus parity [<file|pack>...]      Check the search rules play levels as the game does
+    rs-griphus pareto [<file|pack>...]      Print as TOML the solutions of levels where no other
+                                            one takes fewer steps and less energy
+    rs-griphus solve <level> [--mode dfs|bfs|pushes|energy] [--max-states N] [--timeout S]
+                     [--seed N]
+                                            Solve a level file or pack/id, printing the solution
+                                            and the search statistics as JSON";
+
+/// Run the subcommand given in args, returning the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    match args[0].as_str() {
+        "import" if args.len() == 3 => import(&args[1], &args[2]),
+        "check" => check(&args[1..]),
+        "verify" if args.len() == 3 => verify_moves(&args[1], &args[2]),
+        "verify" if args.len() == 2 => verify_solutions(&args[1]),
+        "parity" => parity(&args[1..]),
+        "pareto" => pareto(&args[1..]),
+        "solve" if args.len() % 2 == 0 => solve_level(&args[1], &args[2..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+/// Convert a Sokoban collection to level files, and a pack manifest listing them.
+/// The output directory can then be used in the level search path.
+fn import(file: &str, out_dir: &str) -> i32 {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            return 1;
+        }
+    };
+    let name = Path::new(file)
+        .file_stem()
+        .map_or(String::from("sokoban"), |stem| {
+            stem.to_string_lossy().to_string()
+        });
+    let out_dir = Path::new(out_dir);
+    if let Err(err) = fs::create_dir_all(out_dir.join("packs")) {
+        eprintln!("{}: {}", out_dir.display(), err);
+        return 1;
+    }
+    let mut levels = vec![];
+    let mut code = 0;
+    for (i, level) in sokoban::parse_collection(&content).iter().enumerate() {
+        let id = format!("{}_{:03}", name, i + 1);
+        match sokoban::to_griphus(level) {
+            Ok(level_content) => {
+                let level_file = format!("{}.txt", id);
+                if let Err(err) = fs::write(out_dir.join(&level_file), level_content) {
+                    eprintln!("{}: {}", level_file, err);
+                    return 1;
+                }
+                levels.push(PackLevel {
+                    id,
+                    title: level
+                        .title
+                        .clone()
+                        .unwrap_or_else(|| format!("{} {}", name, i + 1)),
+                    file: format!("../{}", level_file),
+                });
+            }
+            Err(err) => {
+                eprintln!("{} level {}: {}", file, i + 1, err);
+                code = 1;
+            }
+        }
+    }
+    let pack = LevelPack::new(&name, &name, levels);
+    let manifest = out_dir.join("packs").join(format!("{}.toml", name));
+    let written = toml::to_string(&pack)
+        .map_err(|err| err.to_string())
+        .and_then(|content| fs::write(&manifest, content).map_err(|err| err.to_string()));
+    match written {
+        Ok(()) => println!(
+            "Imported {} levels in {}",
+            pack.levels.len(),
+            manifest.display()
+        ),
+        Err(err) => {
+            eprintln!("{}: {}", manifest.display(), err);
+            return 1;
+        }
+    }
+    code
+}
+
+/// Levels given as level files or `.toml` pack manifests on disk, or names of packs in the assets.
+/// Every pack of the assets when there is no argument.
+fn collect_levels(args: &[String]) -> Vec<(String, Result<LevelDescription, LevelError>)> {
+    let mut targets = args.to_vec();
+    if targets.is_empty() {
+        targets = assets::list_packs();
+    }
+    let mut levels: Vec<(String, Result<LevelDescription, LevelError>)> = vec![];
+    for target in targets.iter() {
+        let path = Path::new(target);
+        if path.is_file() && target.ends_with(".toml") {
+            let pack = match fs::read_to_string(path) {
+                Ok(content) => LevelPack::parse(target, &content),
+                Err(err) => Err(LevelError {
+                    file: target.clone(),
+                    line: 0,
+                    column: 0,
+                    kind: LevelErrorKind::Io(err.to_string()),
+                }),
+            };
+            match pack {
+                Ok(pack) => {
+                    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                    for level in pack.levels.iter() {
+                        let file = dir.join(&level.file).to_string_lossy().to_string();
+                        levels.push((file.clone(), load_level_from_file(&file)));
+                    }
+                }
+                Err(err) => levels.push((target.clone(), Err(err))),
+            }
+        } else if path.is_file() {
+            levels.push((target.clone(), load_level_from_file(target)));
+        } else {
+            let manifest = if target.ends_with(".toml") {
+                target.clone()
+            } else {
+                format!("{}/{}.toml", PACKS_DIR, target)
+            };
+            match LevelPack::load(&manifest) {
+                Ok(pack) => {
+                    for index in 0..pack.levels.len() {
+                        let file = pack.level_path(index).unwrap();
+                        levels.push((file.clone(), read_level(&file)));
+                    }
+                }
+                Err(err) => levels.push((manifest, Err(err))),
+            }
+        }
+    }
+    levels
+}
+
+/// Check levels for errors and problems making them unsolvable.
+/// Warnings are reported without changing the exit code.
+fn check(args: &[String]) -> i32 {
+    let levels = collect_levels(args);
+    let mut failed = 0;
+    let mut warned = 0;
+    for (file, description) in levels.iter() {
+        let (problems, warnings) = match description {
+            Ok(description) => {
+                let report = check_level(description);
+                (
+                    report
+                        .problems
+                        .iter()
+                        .map(|problem| format!("{}: {}", file, problem))
+                        .collect::<Vec<_>>(),
+                    report.warnings,
+                )
+            }
+            Err(err) => (vec![err.to_string()], vec![]),
+        };
+        if !problems.is_empty() {
+            failed += 1;
+        }
+        if !warnings.is_empty() {
+            warned += 1;
+        }
+        for problem in problems.iter() {
+            eprintln!("{}", problem);
+        }
+        for warning in warnings.iter() {
+            eprintln!("{}: warning: {}", file, warning);
+        }
+    }
+    println!(
+        "Checked {} levels, {} with problems, {} with warnings",
+        levels.len(),
+        failed,
+        warned
+    );
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// States explored per level when checking the parity of the rules with the game.
+const PARITY_STATES: usize = 500;
+
+/// Check the GameState rules used by the solvers agree with the game systems on levels.
+fn parity(args: &[String]) -> i32 {
+    let levels = collect_levels(args);
+    let mut failed = 0;
+    for (file, description) in levels.iter() {
+        let result = description
+            .as_ref()
+            .map_err(|err| err.to_string())
+            .and_then(|description| check_parity(description, PARITY_STATES));
+        match result {
+            Ok(states) => println!("{}: {} states agree", file, states),
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                failed += 1;
+            }
+        }
+    }
+    println!("Checked {} levels, {} disagree", levels.len(), failed);
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+#[derive(Serialize)]
+struct ParetoExport {
+    levels: Vec<ParetoLevel>,
+}
+#[derive(Serialize)]
+struct ParetoLevel {
+    file: String,
+    /// False when the search gave up, solutions may then be missing
+    complete: bool,
+    front: Vec<ParetoSolution>,
+}
+#[derive(Serialize)]
+struct ParetoSolution {
+    steps: i32,
+    energy: i32,
+    moves: String,
+}
+
+/// Print the Pareto front of the steps and energy of the solutions of levels.
+fn pareto(args: &[String]) -> i32 {
+    let mut export = ParetoExport { levels: vec![] };
+    let mut code = 0;
+    for (file, description) in collect_levels(args).iter() {
+        let rules = description
+            .as_ref()
+            .map_err(|err| err.to_string())
+            .and_then(Rules::new);
+        match rules {
+            Ok((rules, start)) => {
+                let front = pareto_front(&rules, &start, DEFAULT_MAX_STATES);
+                if front.solutions.is_empty() {
+                    eprintln!("{}: no solution", file);
+                    code = 1;
+                }
+                export.levels.push(ParetoLevel {
+                    file: file.clone(),
+                    complete: front.complete,
+                    front: front
+                        .solutions
+                        .iter()
+                        .map(|solution| ParetoSolution {
+                            steps: solution.steps,
+                            energy: solution.energy,
+                            moves: solution.moves(),
+                        })
+                        .collect(),
+                });
+            }
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                code = 1;
+            }
+        }
+    }
+    match toml::to_string(&export) {
+        Ok(content) => print!("{}", content),
+        Err(err) => {
+            eprintln!("{}", err);
+            code = 1;
+        }
+    }
+    code
+}
+
+#[derive(Serialize)]
+struct SolveStats {
+    level: String,
+    mode: String,
+    /// Seed of the dfs exploration order
+    seed: u64,
+    solution: Option<SolveSolution>,
+    /// States whose successors were explored
+    explored: usize,
+    duplicates: u64,
+    dead_ends: u64,
+    cache_size: usize,
+    elapsed_secs: f64,
+    /// True when every state was explored, without reaching the max states or the timeout
+    exhaustive: bool,
+}
+#[derive(Serialize)]
+struct SolveSolution {
+    steps: i32,
+    energy: i32,
+    moves: String,
+}
+
+/// Solve the level with the AI or a solver, and print the solution and statistics as JSON.
+/// `dfs` explores the whole level like the AI and keeps the solution using the least energy.
+fn solve_level(level: &str, options: &[String]) -> i32 {
+    let mut mode = "bfs";
+    let mut max_states = DEFAULT_MAX_STATES;
+    let mut timeout = None;
+    let mut seed = DEFAULT_SEED;
+    for option in options.chunks(2) {
+        let parsed = match option[0].as_str() {
+            "--mode" if ["dfs", "bfs", "pushes", "energy"].contains(&option[1].as_str()) => {
+                mode = option[1].as_str();
+                true
+            }
+            "--max-states" => option[1].parse().map(|max| max_states = max).is_ok(),
+            "--seed" => option[1].parse().map(|n| seed = n).is_ok(),
+            "--timeout" => option[1]
+                .parse()
+                .map(|secs| timeout = Some(Duration::from_secs_f64(secs)))
+                .is_ok(),
+            _ => false,
+        };
+        if !parsed {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    }
+    let description = match find_level(level) {
+        Ok(description) => description,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let stats = if mode == "dfs" {
+        let mut game = Game::with_rules(&description);
+        let mut ai = AI::new(AiMode::Explore, seed);
+        ai.set_max_states(max_states);
+        ai.explore(&mut game, timeout);
+        SolveStats {
+            level: level.to_string(),
+            mode: mode.to_string(),
+            seed,
+            solution: ai
+                .best_solution()
+                .map(|(steps, energy, moves)| SolveSolution {
+                    steps,
+                    energy,
+                    moves,
+                }),
+            explored: ai.searches as usize,
+            duplicates: ai.duplicates,
+            dead_ends: ai.dead_ends,
+            cache_size: ai.cache_size(),
+            elapsed_secs: ai.elapsed().as_secs_f64(),
+            exhaustive: ai.finished && !ai.gave_up,
+        }
+    } else {
+        let metric = match mode {
+            "pushes" => Metric::Pushes,
+            "energy" => Metric::Energy,
+            _ => Metric::Moves,
+        };
+        let (rules, start) = match Rules::new(&description) {
+            Ok(rules) => rules,
+            Err(err) => {
+                eprintln!("{}: {}", level, err);
+                return 1;
+            }
+        };
+        let start_time = Instant::now();
+        let search = solve(&rules, &start, metric, max_states, timeout);
+        SolveStats {
+            level: level.to_string(),
+            mode: mode.to_string(),
+            seed,
+            solution: search.solution.as_ref().map(|solution| SolveSolution {
+                steps: solution.steps,
+                energy: solution.energy,
+                moves: solution.moves(),
+            }),
+            explored: search.explored,
+            duplicates: search.duplicates as u64,
+            dead_ends: search.dead_ends as u64,
+            cache_size: search.states,
+            elapsed_secs: start_time.elapsed().as_secs_f64(),
+            exhaustive: search.complete,
+        }
+    };
+    match serde_json::to_string_pretty(&stats) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    }
+    if stats.solution.is_some() {
+        0
+    } else {
+        1
+    }
+}
+
+/// Level from a file on disk, or from the assets given as `pack/id`.
+fn find_level(level: &str) -> Result<LevelDescription, String> {
+    if Path::new(level).is_file() {
+        return load_level_from_file(level).map_err(|err| err.to_string());
+    }
+    let packs = LevelPacks::load().map_err(|err| err.to_string())?;
+    let file = packs
+        .find(level)
+        .and_then(|level| packs.level_path(level))
+        .ok_or_else(|| format!("{}: no such file nor pack/id level", level))?;
+    read_level(&file).map_err(|err| err.to_string())
+}
+
+/// Play the moves on the level, the exit code is 0 only when it is solved.
+fn verify_moves(level: &str, moves: &str) -> i32 {
+    let result = find_level(level)
+        .and_then(|description| parse_inputs(moves).map(|inputs| verify(&description, &inputs)));
+    match result {
+        Ok(verdict) => {
+            let state = match verdict.state {
+                TurnState::PlayerAtExit => "solved",
+                TurnState::PlayerDead => "dead",
+                TurnState::Running => "running",
+            };
+            println!(
+                "{}: {} after {} steps, {} energy",
+                level, state, verdict.steps, verdict.energy
+            );
+            if verdict.state == TurnState::PlayerAtExit {
+                0
+            } else {
+                1
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Verify the move files of a solutions directory, laid out as `<pack>/<id>.txt`.
+fn verify_solutions(dir: &str) -> i32 {
+    let mut files = vec![];
+    let packs = match fs::read_dir(dir) {
+        Ok(packs) => packs,
+        Err(err) => {
+            eprintln!("{}: {}", dir, err);
+            return 1;
+        }
+    };
+    for pack in packs.filter_map(|entry| entry.ok()) {
+        if let Ok(levels) = fs::read_dir(pack.path()) {
+            for level in levels.filter_map(|entry| entry.ok()) {
+                let path = level.path();
+                if path.extension().map_or(false, |ext| ext == "txt") {
+                    files.push(path);
+                }
+            }
+        }
+    }
+    files.sort();
+    let mut code = 0;
+    for file in files.iter() {
+        let level = format!(
+            "{}/{}",
+            file.parent()
+                .unwrap()
+                .file_name()
+                .unwrap()
+                .to_string_lossy(),
+            file.file_stem().unwrap().to_string_lossy()
+        );
+        match fs::read_to_string(file) {
+            Ok(moves) => {
+                if verify_moves(&level, &moves) != 0 {
+                    code = 1;
+                }
+            }
+            Err(err) => {
+                eprintln!("{}: {}", file.display(), err);
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOLUTIONS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/solutions");
+
+    #[test]
+    fn every_shipped_level_has_a_solution() {
+        let packs = LevelPacks::load().unwrap();
+        for pack in packs.packs.iter() {
+            for level in pack.levels.iter() {
+                let file = format!("{}/{}/{}.txt", SOLUTIONS_DIR, pack.name, level.id);
+                assert!(Path::new(&file).is_file(), "Missing solution {}", file);
+            }
+        }
+    }
+
+    #[test]
+    fn solutions_solve_their_level() {
+        assert_eq!(verify_solutions(SOLUTIONS_DIR), 0);
+    }
+}
@@ -1,7 +1,6 @@
-use bracket_lib::prelude::*;
 use legion::prelude::*;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Cardinal {
     N,
     S,
@@ -29,6 +28,26 @@ impl Cardinal {
             _ => panic!("Can only get_delta_xy for N,S,E,W"),
         }
     }
+    /// Direction of a laser going in this direction after a reflector of the given orientation.
+    pub fn reflect(&self, orientation: Cardinal) -> Cardinal {
+        match orientation {
+            Cardinal::NE => match self {
+                Cardinal::N => Cardinal::E,
+                Cardinal::S => Cardinal::W,
+                Cardinal::E => Cardinal::N,
+                Cardinal::W => Cardinal::S,
+                _ => Cardinal::N,
+            },
+            Cardinal::NW => match self {
+                Cardinal::N => Cardinal::W,
+                Cardinal::S => Cardinal::E,
+                Cardinal::W => Cardinal::N,
+                Cardinal::E => Cardinal::S,
+                _ => Cardinal::N,
+            },
+            _ => Cardinal::N,
+        }
+    }
 }
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Position {
@@ -36,27 +55,78 @@ pub struct Position {
     pub y: i32,
 }
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Renderable {
-    pub glyph: FontCharType,
-    pub fg: RGB,
-    pub bg: RGB,
-    pub render_order: i32, // 0 will be in front, masking 1 which will masked 2 etc...
-}
-#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ActivationKind {
     Laser,
     Weight,
 }
 #[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActivationBehaviour {
+    /// Active only while triggered
+    Momentary,
+    /// Stays active once triggered
+    Latching,
+    /// Stays active for the given number of turns after the trigger is gone
+    Timed(i32),
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Activable {
     pub active: bool,
     pub kind: ActivationKind,
+    pub behaviour: ActivationBehaviour,
+    /// Latching activable has been triggered
+    pub latched: bool,
+    /// Last step a Timed activable was triggered
+    pub last_triggered: Option<i32>,
+}
+
+/// Boolean expression over activators of type T.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Logic<T> {
+    Is(T),
+    All(Vec<Logic<T>>),
+    Any(Vec<Logic<T>>),
+    /// True when exactly one of the sub-expressions is true.
+    One(Vec<Logic<T>>),
+    Not(Box<Logic<T>>),
+}
+impl<T> Logic<T> {
+    pub fn eval<F: Fn(&T) -> bool>(&self, is_active: &F) -> bool {
+        match self {
+            Logic::Is(x) => is_active(x),
+            Logic::All(xs) => xs.iter().all(|x| x.eval(is_active)),
+            Logic::Any(xs) => xs.iter().any(|x| x.eval(is_active)),
+            Logic::One(xs) => xs.iter().filter(|x| x.eval(is_active)).count() == 1,
+            Logic::Not(x) => !x.eval(is_active),
+        }
+    }
+    pub fn map<U, F: Fn(&T) -> U>(&self, f: &F) -> Logic<U> {
+        match self {
+            Logic::Is(x) => Logic::Is(f(x)),
+            Logic::All(xs) => Logic::All(xs.iter().map(|x| x.map(f)).collect()),
+            Logic::Any(xs) => Logic::Any(xs.iter().map(|x| x.map(f)).collect()),
+            Logic::One(xs) => Logic::One(xs.iter().map(|x| x.map(f)).collect()),
+            Logic::Not(x) => Logic::Not(Box::new(x.map(f))),
+        }
+    }
+    /// All the activators used in the expression.
+    pub fn leaves(&self) -> Vec<&T> {
+        match self {
+            Logic::Is(x) => vec![x],
+            Logic::All(xs) | Logic::Any(xs) | Logic::One(xs) => {
+                xs.iter().flat_map(|x| x.leaves()).collect()
+            }
+            Logic::Not(x) => x.leaves(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Door {
+    /// Label of the door in the level legend
+    pub name: String,
     pub opened: bool,
-    pub activations: Vec<Entity>,
+    /// Opens the door when true.
+    pub logic: Logic<Entity>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
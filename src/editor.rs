@@ -0,0 +1,348 @@
+//! In-game level editor, working directly on the World of the level.
+//! In a level without legend, the exit door is wired to every plate and receptor after each edit.
+//! The doors of a legend are kept, and the activators they are wired to cannot be removed.
+use crate::assets;
+use crate::components::{
+    Activable, ActivationBehaviour, ActivationKind, Cardinal, Door, Player, Position,
+};
+use crate::level::{self, LevelInfo};
+use crate::map::{Map, TileType};
+use crate::systems::build_laser_systems;
+use crate::RunState;
+use bracket_lib::prelude::*;
+use legion::prelude::*;
+use std::fs;
+
+const EDITOR_DIR: &str = "editor";
+const BLANK_WIDTH: usize = 16;
+const BLANK_HEIGHT: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tool {
+    Wall,
+    Floor,
+    Exit,
+    Player,
+    Block,
+    Plate,
+    Receptor,
+    Laser,
+    Reflector,
+}
+const TOOLS: [(VirtualKeyCode, Tool, &str); 9] = [
+    (VirtualKeyCode::Key1, Tool::Wall, "Wall"),
+    (VirtualKeyCode::Key2, Tool::Floor, "Floor"),
+    (VirtualKeyCode::Key3, Tool::Exit, "Exit"),
+    (VirtualKeyCode::Key4, Tool::Player, "Player"),
+    (VirtualKeyCode::Key5, Tool::Block, "Block"),
+    (VirtualKeyCode::Key6, Tool::Plate, "Plate"),
+    (VirtualKeyCode::Key7, Tool::Receptor, "Receptor"),
+    (VirtualKeyCode::Key8, Tool::Laser, "Laser"),
+    (VirtualKeyCode::Key9, Tool::Reflector, "Reflector"),
+];
+
+pub struct Editor {
+    x: i32,
+    y: i32,
+    tool: Tool,
+    laser: Cardinal,
+    reflector: Cardinal,
+    /// Level without doors in its legend, its exit door is wired again after each edit
+    auto_wired: bool,
+    /// Level before play-testing, restored when coming back to the editor
+    saved: Option<String>,
+    message: String,
+    schedule: Schedule,
+}
+impl Editor {
+    /// Editor on the level, or on a blank level when None.
+    pub fn new(
+        ecs: &mut World,
+        rsrc: &mut Resources,
+        description: Option<level::LevelDescription>,
+    ) -> Editor {
+        let description = description.unwrap_or_else(|| {
+            level::parse_level(EDITOR_DIR, &blank_level()).expect("Blank level is valid")
+        });
+        level::build_level(ecs, rsrc, None, &description);
+        Editor {
+            x: description.width / 2,
+            y: description.height / 2,
+            tool: Tool::Wall,
+            laser: Cardinal::N,
+            reflector: Cardinal::NE,
+            auto_wired: level::is_auto_wired(&description),
+            saved: None,
+            message: String::new(),
+            schedule: build_laser_systems(),
+        }
+    }
+    pub fn is_playtesting(&self) -> bool {
+        self.saved.is_some()
+    }
+    /// Index the map and trace the lasers, without playing any turn.
+    pub fn run_systems(&mut self, ecs: &mut World, rsrc: &mut Resources) {
+        self.schedule.execute(ecs, rsrc);
+    }
+    /// Arrows move the cursor, 1-9 choose the tool, Space uses it and Delete clears the tile.
+    /// F5 play-tests the level and F2 saves it.
+    pub fn input(&mut self, ecs: &mut World, rsrc: &mut Resources, ctx: &mut BTerm) -> RunState {
+        let (width, height) = {
+            let map = rsrc.get::<Map>().unwrap();
+            (map.width, map.height)
+        };
+        match ctx.key {
+            None => {}
+            Some(key) => match key {
+                VirtualKeyCode::Left => self.x = (self.x - 1).max(0),
+                VirtualKeyCode::Right => self.x = (self.x + 1).min(width - 1),
+                VirtualKeyCode::Up => self.y = (self.y - 1).max(0),
+                VirtualKeyCode::Down => self.y = (self.y + 1).min(height - 1),
+                VirtualKeyCode::Space => self.apply(ecs, rsrc),
+                VirtualKeyCode::Delete | VirtualKeyCode::Back => {
+                    if self.delete_at(ecs, self.x, self.y, |_| true) {
+                        self.wire(ecs, rsrc);
+                    }
+                }
+                VirtualKeyCode::F5 => return self.start_playtest(ecs, rsrc),
+                VirtualKeyCode::F2 => self.save(ecs, rsrc),
+                VirtualKeyCode::Escape => {
+                    return RunState::MainMenu {
+                        menu_selection: crate::gui::MainMenuSelection::Editor,
+                    }
+                }
+                key => {
+                    if let Some(&(_, tool, _)) = TOOLS.iter().find(|(k, _, _)| *k == key) {
+                        if tool == self.tool && tool == Tool::Laser {
+                            self.laser = match self.laser {
+                                Cardinal::N => Cardinal::E,
+                                Cardinal::E => Cardinal::S,
+                                Cardinal::S => Cardinal::W,
+                                _ => Cardinal::N,
+                            };
+                        } else if tool == self.tool && tool == Tool::Reflector {
+                            self.reflector = match self.reflector {
+                                Cardinal::NE => Cardinal::NW,
+                                _ => Cardinal::NE,
+                            };
+                        }
+                        self.tool = tool;
+                    }
+                }
+            },
+        }
+        RunState::Editor
+    }
+    /// Use the current tool on the tile under the cursor.
+    fn apply(&mut self, ecs: &mut World, rsrc: &mut Resources) {
+        let (x, y) = (self.x, self.y);
+        let tiletype = rsrc.get::<Map>().unwrap().tiletype(x, y);
+        match self.tool {
+            Tool::Wall => {
+                if !self.delete_at(ecs, x, y, |_| true) {
+                    return;
+                }
+                rsrc.get_mut::<Map>()
+                    .unwrap()
+                    .set_tiletype(x, y, TileType::Wall);
+            }
+            // The door of an exit stays when the exit is placed again.
+            Tool::Exit if tiletype == TileType::Exit => {}
+            Tool::Floor | Tool::Exit => {
+                if !self.delete_at(ecs, x, y, |_| false) {
+                    return;
+                }
+                let tile = if self.tool == Tool::Exit {
+                    TileType::Exit
+                } else {
+                    TileType::Floor
+                };
+                rsrc.get_mut::<Map>().unwrap().set_tiletype(x, y, tile);
+            }
+            Tool::Plate => {
+                if !self.delete_at(ecs, x, y, |layer| layer != Layer::Top) {
+                    return;
+                }
+                level::spawn_weight_plate(ecs, x, y, ActivationBehaviour::Momentary);
+            }
+            tool => {
+                // A receptor cannot share its tile with a plate.
+                let receptor = tool == Tool::Receptor;
+                if !self.delete_at(ecs, x, y, |layer| layer != Layer::Plate || receptor) {
+                    return;
+                }
+                if tool == Tool::Player {
+                    let players = <(Read<Position>,)>::query()
+                        .filter(tag::<Player>())
+                        .iter_entities(ecs)
+                        .map(|(entity, _)| entity)
+                        .collect::<Vec<_>>();
+                    for player in players {
+                        ecs.delete(player);
+                    }
+                }
+                match tool {
+                    Tool::Player => level::spawn_player(ecs, x, y),
+                    Tool::Block => level::spawn_block(ecs, x, y),
+                    Tool::Receptor => level::spawn_laser_receptor(ecs, x, y),
+                    Tool::Laser => level::spawn_laser(ecs, x, y, self.laser),
+                    _ => level::spawn_laser_reflector(ecs, x, y, self.reflector),
+                };
+            }
+        }
+        if tiletype == TileType::Wall && self.tool != Tool::Wall && self.tool != Tool::Exit {
+            rsrc.get_mut::<Map>()
+                .unwrap()
+                .set_tiletype(x, y, TileType::Floor);
+        }
+        self.wire(ecs, rsrc);
+    }
+    fn wire(&self, ecs: &mut World, rsrc: &Resources) {
+        if self.auto_wired {
+            let map = rsrc.get::<Map>().unwrap();
+            level::wire_exit_door(ecs, &map);
+        }
+    }
+    /// Delete the door on the tile, so that none is left under what is placed there,
+    /// and the entities in the layers accepted by f.
+    /// Returns false, deleting nothing, when one of them is wired to a door of the legend.
+    fn delete_at<F: Fn(Layer) -> bool>(&mut self, ecs: &mut World, x: i32, y: i32, f: F) -> bool {
+        let wired = if self.auto_wired {
+            vec![]
+        } else {
+            <(Read<Door>,)>::query()
+                .iter(ecs)
+                .flat_map(|(door,)| door.logic.leaves().into_iter().copied().collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        let mut entities = <(Read<Position>,)>::query()
+            .iter_entities(ecs)
+            .filter(|(_, (pos,))| pos.x == x && pos.y == y)
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        entities.retain(|&entity| {
+            if ecs.get_component::<Door>(entity).is_some() {
+                return true;
+            }
+            let layer = match ecs.get_component::<Activable>(entity).map(|a| a.kind) {
+                Some(ActivationKind::Weight) => Layer::Plate,
+                Some(ActivationKind::Laser) => Layer::Receptor,
+                None => Layer::Top,
+            };
+            f(layer)
+        });
+        if entities.iter().any(|entity| wired.contains(entity)) {
+            self.message = format!("({},{}) is wired to a door of the legend", x, y);
+            return false;
+        }
+        for entity in entities {
+            ecs.delete(entity);
+        }
+        true
+    }
+    fn level_text(ecs: &World, rsrc: &Resources) -> String {
+        let map = rsrc.get::<Map>().unwrap();
+        let info = rsrc.get::<LevelInfo>().unwrap();
+        level::save_level_with_info(ecs, &map, &info)
+    }
+    /// Play the level from its edited state, it is parsed back first so it is checked like a file.
+    fn start_playtest(&mut self, ecs: &mut World, rsrc: &mut Resources) -> RunState {
+        let content = Editor::level_text(ecs, rsrc);
+        match level::parse_level(EDITOR_DIR, &content) {
+            Ok(description) => {
+                level::build_level(ecs, rsrc, None, &description);
+                self.saved = Some(content);
+                self.message.clear();
+                RunState::GameAwaitingInput
+            }
+            Err(err) => {
+                self.message = err.message();
+                RunState::Editor
+            }
+        }
+    }
+    /// Come back to the level as it was before play-testing.
+    pub fn stop_playtest(&mut self, ecs: &mut World, rsrc: &mut Resources) {
+        if let Some(content) = self.saved.take() {
+            let description =
+                level::parse_level(EDITOR_DIR, &content).expect("Play-tested level is valid");
+            level::build_level(ecs, rsrc, None, &description);
+        }
+    }
+    /// Write the level in the editor directory of the user data directory,
+    /// as the first free level_NNN.txt.
+    fn save(&mut self, ecs: &World, rsrc: &Resources) {
+        let dir = match assets::user_data_dir() {
+            Some(dir) => dir.join(EDITOR_DIR),
+            None => {
+                self.message = String::from("Cannot save: no user data directory");
+                return;
+            }
+        };
+        let content = Editor::level_text(ecs, rsrc);
+        let file = (1..)
+            .map(|i| dir.join(format!("level_{:03}.txt", i)))
+            .find(|file| !file.exists())
+            .unwrap();
+        self.message = match fs::create_dir_all(&dir).and_then(|_| fs::write(&file, content)) {
+            Ok(()) => format!("Saved to {}", file.display()),
+            Err(err) => format!("Cannot save {}: {}", file.display(), err),
+        };
+    }
+    /// Draw the cursor over the level drawn at start_y, and the tools.
+    pub fn draw(&self, map: &Map, ctx: &mut BTerm, start_y: i32) {
+        let start_x = (crate::TERM_WIDTH - map.width) / 2;
+        ctx.set_bg(start_x + self.x, start_y + self.y, RGB::named(DARK_BLUE));
+        for (i, &(_, tool, name)) in TOOLS.iter().enumerate() {
+            let fg = if tool == self.tool {
+                RGB::named(MAGENTA)
+            } else {
+                RGB::named(WHITE)
+            };
+            let name = match tool {
+                Tool::Laser => format!("{} {:?}", name, self.laser),
+                Tool::Reflector => format!("{} {:?}", name, self.reflector),
+                _ => String::from(name),
+            };
+            ctx.print_color(
+                1 + (i as i32 % 3) * 13,
+                1 + i as i32 / 3,
+                fg,
+                RGB::named(BLACK),
+                format!("{}:{}", i + 1, name),
+            );
+        }
+        ctx.print(1, 5, "Space:use Del:clear F5:test F2:save");
+        ctx.print(1, 6, format!("Cursor: {},{}", self.x, self.y));
+        ctx.print_color(1, 8, RGB::named(ORANGE), RGB::named(BLACK), &self.message);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Layer {
+    /// Weight plates, under the other entities
+    Plate,
+    /// Laser receptors, that cannot share their tile with a plate
+    Receptor,
+    Top,
+}
+
+/// A walled room with the player and an exit.
+fn blank_level() -> String {
+    let mut content = String::new();
+    for y in 0..BLANK_HEIGHT {
+        for x in 0..BLANK_WIDTH {
+            content.push(if y == 0 && x == BLANK_WIDTH / 2 {
+                'E'
+            } else if x == 0 || y == 0 || x == BLANK_WIDTH - 1 || y == BLANK_HEIGHT - 1 {
+                '#'
+            } else if (x, y) == (2, BLANK_HEIGHT - 3) {
+                '@'
+            } else {
+                '.'
+            });
+        }
+        content.push('\n');
+    }
+    content
+}
@@ -0,0 +1,393 @@
+//! Game rules running on a level without any window or rendering,
+//! to play, inspect or verify it from the command line and the solvers.
+use crate::components::{
+    Activable, ActivationBehaviour, Block, Cardinal, Door, Laser, Player, Position, ReflectsLaser,
+};
+use crate::game_state::{GameState, Rules, NEVER};
+use crate::level::{build_level, LevelDescription};
+use crate::player::{try_input, PlayerInput};
+use crate::systems::build_systems;
+use crate::turn_history::{TurnState, TurnsHistory};
+use legion::prelude::*;
+use std::collections::{HashSet, VecDeque};
+
+/// Result of one step of the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    /// The input had no effect, no turn was played
+    Blocked,
+    Running,
+    Dead,
+    Solved,
+}
+impl From<TurnState> for Outcome {
+    fn from(state: TurnState) -> Outcome {
+        match state {
+            TurnState::Running => Outcome::Running,
+            TurnState::PlayerDead => Outcome::Dead,
+            TurnState::PlayerAtExit => Outcome::Solved,
+        }
+    }
+}
+
+pub struct Game {
+    pub ecs: World,
+    pub rsrc: Resources,
+    schedule: Schedule,
+}
+impl Game {
+    /// Build the level in a new World, and run the schedule so the map is up to date.
+    pub fn new(description: &LevelDescription, schedule: Schedule) -> Game {
+        let universe = Universe::new();
+        let mut game = Game {
+            ecs: universe.create_world(),
+            rsrc: Resources::default(),
+            schedule,
+        };
+        build_level(&mut game.ecs, &mut game.rsrc, None, description);
+        // Doors opened at the start only update the map on the second run, as in a turn.
+        game.run_systems();
+        game.run_systems();
+        game
+    }
+    /// Game with all the systems, ready to be played with step.
+    pub fn with_rules(description: &LevelDescription) -> Game {
+        Game::new(description, build_systems())
+    }
+    pub fn run_systems(&mut self) {
+        self.schedule.execute(&mut self.ecs, &mut self.rsrc);
+    }
+    pub fn state(&self) -> TurnState {
+        self.rsrc.get::<TurnsHistory>().unwrap().state
+    }
+    /// Play the input as the player would, once the player is dead or at the exit
+    /// the input is ignored and the outcome stays the same.
+    pub fn step(&mut self, input: PlayerInput) -> Outcome {
+        if self.state() != TurnState::Running {
+            return self.state().into();
+        }
+        let actions = try_input(input, &mut self.ecs, &mut self.rsrc);
+        if actions.is_empty() {
+            return Outcome::Blocked;
+        }
+        self.rsrc
+            .get_mut::<TurnsHistory>()
+            .unwrap()
+            .play_turn(&mut self.ecs, Some(input), actions);
+        // The game runs the systems once for the turn and once more before drawing,
+        // doors opened during the turn only update the map on the second run.
+        self.run_systems();
+        self.run_systems();
+        self.state().into()
+    }
+    /// The World as a GameState of the rules of its level.
+    pub fn snapshot(&self, rules: &Rules) -> GameState {
+        let history = self.rsrc.get::<TurnsHistory>().unwrap();
+        let idx = |pos: &Position| rules.xy_idx(pos.x, pos.y);
+        let player = <(Read<Position>,)>::query()
+            .filter(tag::<Player>())
+            .iter(&self.ecs)
+            .map(|(pos,)| idx(&pos))
+            .next()
+            .unwrap();
+        let mut blocks = <(Read<Position>,)>::query()
+            .filter(tag::<Block>())
+            .iter(&self.ecs)
+            .map(|(pos,)| idx(&pos))
+            .collect::<Vec<_>>();
+        blocks.sort();
+        let mut lasers = <(Tagged<Laser>, Read<Position>)>::query()
+            .iter(&self.ecs)
+            .map(|(laser, pos)| (idx(&pos), laser.direction))
+            .collect::<Vec<_>>();
+        lasers.sort_by_key(|&(idx, _)| idx);
+        let mut reflectors = <(Read<ReflectsLaser>, Read<Position>)>::query()
+            .iter(&self.ecs)
+            .map(|(reflector, pos)| (idx(&pos), reflector.orientation))
+            .collect::<Vec<_>>();
+        reflectors.sort_by_key(|&(idx, _)| idx);
+        let activables = <(Read<Activable>, Read<Position>)>::query()
+            .iter(&self.ecs)
+            .map(|(activable, pos)| (idx(&pos), *activable))
+            .collect::<Vec<_>>();
+        let memory = rules
+            .activables
+            .iter()
+            .map(|&(tile, _, behaviour)| {
+                let activable = activables.iter().find(|(a, _)| *a == tile).unwrap().1;
+                match behaviour {
+                    ActivationBehaviour::Momentary => 0,
+                    ActivationBehaviour::Latching => activable.latched as u16,
+                    ActivationBehaviour::Timed(turns) => activable
+                        .last_triggered
+                        .map(|last| history.steps - last)
+                        .filter(|&since| since <= turns)
+                        .map_or(NEVER, |since| since as u16),
+                }
+            })
+            .collect();
+        let doors = <(Read<Door>, Read<Position>)>::query()
+            .iter(&self.ecs)
+            .map(|(door, pos)| (idx(&pos), door.opened))
+            .collect::<Vec<_>>();
+        let mut opened = 0;
+        for (i, &(tile, _)) in rules.doors.iter().enumerate() {
+            if doors.iter().any(|&(d, open)| d == tile && open) {
+                opened |= 1 << i;
+            }
+        }
+        GameState {
+            player,
+            blocks,
+            lasers,
+            reflectors,
+            memory,
+            opened,
+            state: history.state,
+        }
+    }
+}
+
+/// Result of playing a sequence of inputs on a level.
+pub struct Verdict {
+    pub state: TurnState,
+    pub steps: i32,
+    pub energy: i32,
+    /// Inputs played, the remaining ones are ignored once the player is dead or at the exit.
+    pub played: usize,
+}
+
+/// Play the inputs on the level with the game rules, as the player would.
+pub fn verify(description: &LevelDescription, inputs: &[PlayerInput]) -> Verdict {
+    let mut game = Game::with_rules(description);
+    let mut played = 0;
+    for &input in inputs.iter() {
+        if game.state() != TurnState::Running {
+            break;
+        }
+        game.step(input);
+        played += 1;
+    }
+    let turn_history = game.rsrc.get::<TurnsHistory>().unwrap();
+    Verdict {
+        state: turn_history.state,
+        steps: turn_history.steps,
+        energy: turn_history.energy_used,
+        played,
+    }
+}
+
+/// Explore up to max_states states of the level with the GameState rules, playing every
+/// path again with the systems to check both reach the same state with the same energy.
+/// Returns the number of states explored.
+pub fn check_parity(description: &LevelDescription, max_states: usize) -> Result<usize, String> {
+    let (rules, start) = Rules::new(description)?;
+    let inputs = [
+        PlayerInput::Move(Cardinal::W),
+        PlayerInput::Move(Cardinal::E),
+        PlayerInput::Move(Cardinal::N),
+        PlayerInput::Move(Cardinal::S),
+        PlayerInput::Actuate,
+    ];
+    let snapshot = Game::with_rules(description).snapshot(&rules);
+    if snapshot != start {
+        return Err(format!(
+            "Initial states differ\n  game:  {:?}\n  rules: {:?}",
+            snapshot, start
+        ));
+    }
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert(start.clone());
+    queue.push_back((start, vec![], 0));
+    while let Some((state, path, energy)) = queue.pop_front() {
+        for &input in inputs.iter() {
+            let mut game = Game::with_rules(description);
+            for &played in path.iter() {
+                game.step(played);
+            }
+            let outcome = game.step(input);
+            let mut moves = path.clone();
+            moves.push(input);
+            let moves_txt = moves.iter().map(|i| i.to_char()).collect::<String>();
+            let (next, used) = match state.step(&rules, input) {
+                Some(next) => next,
+                None if outcome == Outcome::Blocked => continue,
+                None => return Err(format!("{}: blocked by the rules only", moves_txt)),
+            };
+            if outcome == Outcome::Blocked {
+                return Err(format!("{}: blocked by the game only", moves_txt));
+            }
+            let snapshot = game.snapshot(&rules);
+            if snapshot != next {
+                return Err(format!(
+                    "{}: states differ\n  game:  {:?}\n  rules: {:?}",
+                    moves_txt, snapshot, next
+                ));
+            }
+            let game_energy = game.rsrc.get::<TurnsHistory>().unwrap().energy_used;
+            if game_energy != energy + used {
+                return Err(format!(
+                    "{}: energy differs, game {} rules {}",
+                    moves_txt,
+                    game_energy,
+                    energy + used
+                ));
+            }
+            if next.state == TurnState::Running && seen.len() < max_states && !seen.contains(&next)
+            {
+                seen.insert(next.clone());
+                queue.push_back((next, moves, energy + used));
+            }
+        }
+    }
+    Ok(seen.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::parse_level;
+    use crate::level::tests::shipped_levels;
+    use crate::player::parse_inputs;
+
+    const PARITY_STATES: usize = 500;
+
+    #[test]
+    fn shipped_levels_parity() {
+        for (file, description) in shipped_levels().iter() {
+            if let Err(err) = check_parity(description, PARITY_STATES) {
+                panic!("{}: {}", file, err);
+            }
+        }
+    }
+
+    #[test]
+    fn latch_and_timer_parity() {
+        let content = "\
+#####E#
+#@.b..#
+#.x.x.#
+#..b..#
+#######
+
+A: door(5,0) <- plate(2,2), plate(4,2)
+T: timer(2,2,2)
+L: latch(4,2)
+";
+        let description = parse_level("parity", content).unwrap_or_else(|err| panic!("{}", err));
+        check_parity(&description, PARITY_STATES).unwrap();
+    }
+
+    #[test]
+    fn timer_too_long_is_rejected() {
+        let content = format!(
+            "\
+####E#
+#@bx.#
+######
+
+T: timer(3,1,{})
+",
+            NEVER
+        );
+        let description = parse_level("timer", &content).unwrap_or_else(|err| panic!("{}", err));
+        assert!(Rules::new(&description).is_err());
+    }
+
+    #[test]
+    fn level_too_large_is_rejected() {
+        let width = 300;
+        let height = 220;
+        let mut content = String::new();
+        for y in 0..height {
+            for x in 0..width {
+                content.push(match (x, y) {
+                    (1, 0) => 'E',
+                    (1, 1) => '@',
+                    _ if x == 0 || y == 0 || x == width - 1 || y == height - 1 => '#',
+                    _ => '.',
+                });
+            }
+            content.push('\n');
+        }
+        let description = parse_level("large", &content).unwrap_or_else(|err| panic!("{}", err));
+        assert!(Rules::new(&description).is_err());
+    }
+
+    /// State of the game, with the latching and timed state of its activables and the energy used.
+    fn turn_state(game: &Game, rules: &Rules) -> (GameState, Vec<(bool, Option<i32>)>, i32) {
+        let activables = <(Read<Activable>,)>::query()
+            .iter(&game.ecs)
+            .map(|(activable,)| (activable.latched, activable.last_triggered))
+            .collect();
+        let energy = game.rsrc.get::<TurnsHistory>().unwrap().energy_used;
+        (game.snapshot(rules), activables, energy)
+    }
+
+    /// Undo the last turn as the player does, the systems then run for the turn and before drawing.
+    fn undo(game: &mut Game) {
+        game.rsrc
+            .get_mut::<TurnsHistory>()
+            .unwrap()
+            .undo_last_turn(&mut game.ecs);
+        game.run_systems();
+        game.run_systems();
+    }
+
+    /// Redo the last undone turn as the player does.
+    fn redo(game: &mut Game) {
+        game.rsrc
+            .get_mut::<TurnsHistory>()
+            .unwrap()
+            .redo_turn(&mut game.ecs);
+        game.run_systems();
+        game.run_systems();
+    }
+
+    #[test]
+    fn redo_restores_latch_and_timer_until_a_new_turn() {
+        let content = "\
+#####E#
+#@bx..#
+#..bx.#
+#.....#
+#######
+
+A: door(5,0) <- plate(3,1), plate(4,2)
+T: timer(3,1,2)
+L: latch(4,2)
+";
+        let description = parse_level("redo", content).unwrap_or_else(|err| panic!("{}", err));
+        let (rules, _) = Rules::new(&description).unwrap();
+        let mut game = Game::with_rules(&description);
+        let mut states = vec![turn_state(&game, &rules)];
+        // Push a block on the timer, step down and push the other block on the latch
+        for &input in parse_inputs("RDR").unwrap().iter() {
+            assert_eq!(game.step(input), Outcome::Running);
+            states.push(turn_state(&game, &rules));
+        }
+        assert!(states[1].1.contains(&(false, Some(1))));
+        assert!(states[3].1.iter().any(|&(latched, _)| latched));
+        assert!(states[3].1.contains(&(false, Some(3))));
+        assert_ne!(states[3].0.opened, 0);
+
+        undo(&mut game);
+        undo(&mut game);
+        assert_eq!(turn_state(&game, &rules), states[1]);
+        redo(&mut game);
+        assert_eq!(turn_state(&game, &rules), states[2]);
+        redo(&mut game);
+        assert_eq!(turn_state(&game, &rules), states[3]);
+        assert!(game.rsrc.get::<TurnsHistory>().unwrap().redo.is_empty());
+
+        undo(&mut game);
+        assert_eq!(turn_state(&game, &rules), states[2]);
+        assert_eq!(game.step(PlayerInput::Move(Cardinal::W)), Outcome::Running);
+        let played = turn_state(&game, &rules);
+        assert!(game.rsrc.get::<TurnsHistory>().unwrap().redo.is_empty());
+        redo(&mut game);
+        assert_eq!(turn_state(&game, &rules), played);
+        assert_eq!(game.rsrc.get::<TurnsHistory>().unwrap().steps, 3);
+    }
+}
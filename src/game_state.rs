@@ -0,0 +1,374 @@
+//! Compact state of a level and the rules playing a turn on it, without the World.
+//! It replays what the systems do in a turn, so the solvers can search levels quickly,
+//! `game_core::check_parity` checks both agree.
+use crate::components::{ActivationBehaviour, ActivationKind, Cardinal, Logic};
+use crate::legend::Activator;
+use crate::level::{LevelDescription, LevelEntity};
+use crate::map::TileType;
+use crate::player::PlayerInput;
+use crate::turn_history::TurnState;
+
+/// Memory of a Timed activable never triggered, or triggered too long ago to be active.
+/// Timers must last fewer turns, so that the turns since the trigger fit in the memory.
+pub const NEVER: u16 = u16::MAX;
+
+/// What stays the same during the whole level: tiles, plates, receptors and doors.
+pub struct Rules {
+    pub width: i32,
+    pub height: i32,
+    pub tiles: Vec<TileType>,
+    /// Plates and receptors, sorted by tile index
+    pub activables: Vec<(u16, ActivationKind, ActivationBehaviour)>,
+    /// Doors sorted by tile index, with their logic over activables indexes
+    pub doors: Vec<(u16, Logic<usize>)>,
+}
+impl Rules {
+    /// Rules of the level and its initial state.
+    pub fn new(description: &LevelDescription) -> Result<(Rules, GameState), String> {
+        if description.width * description.height > u16::MAX as i32 {
+            return Err(format!(
+                "Level of {}x{} tiles, at most {} tiles are supported",
+                description.width,
+                description.height,
+                u16::MAX
+            ));
+        }
+        let mut rules = Rules {
+            width: description.width,
+            height: description.height,
+            tiles: description.tiles.clone(),
+            activables: vec![],
+            doors: vec![],
+        };
+        let mut player = None;
+        let mut blocks = vec![];
+        let mut lasers = vec![];
+        let mut reflectors = vec![];
+        for &(x, y, entity) in description.entities.iter() {
+            let idx = rules.xy_idx(x, y);
+            match entity {
+                LevelEntity::Player if player.is_some() => {
+                    return Err(String::from("More than one player"))
+                }
+                LevelEntity::Player => player = Some(idx),
+                LevelEntity::Block => blocks.push(idx),
+                LevelEntity::Laser(direction) => lasers.push((idx, direction)),
+                LevelEntity::Reflector(orientation) => reflectors.push((idx, orientation)),
+                LevelEntity::WeightPlate => {
+                    let behaviour = description
+                        .behaviours
+                        .iter()
+                        .find(|&&(bx, by, _)| bx == x && by == y)
+                        .map_or(ActivationBehaviour::Momentary, |&(_, _, behaviour)| {
+                            behaviour
+                        });
+                    if let ActivationBehaviour::Timed(turns) = behaviour {
+                        if turns < 0 || turns >= NEVER as i32 {
+                            return Err(format!(
+                                "Timer of plate ({},{}) lasts {} turns, at most {} are supported",
+                                x,
+                                y,
+                                turns,
+                                NEVER - 1
+                            ));
+                        }
+                    }
+                    rules
+                        .activables
+                        .push((idx, ActivationKind::Weight, behaviour));
+                }
+                LevelEntity::LaserReceptor => rules.activables.push((
+                    idx,
+                    ActivationKind::Laser,
+                    ActivationBehaviour::Momentary,
+                )),
+            }
+        }
+        if description.doors.len() > 64 {
+            return Err(String::from("More than 64 doors"));
+        }
+        rules.activables.sort_by_key(|&(idx, _, _)| idx);
+        for door in description.doors.iter() {
+            let activables = &rules.activables;
+            let width = rules.width;
+            let logic = door.logic.map(&|activator: &Activator| {
+                let (x, y) = activator.position();
+                let idx = (y * width + x) as u16;
+                activables.iter().position(|&(a, _, _)| a == idx).unwrap()
+            });
+            rules.doors.push((rules.xy_idx(door.x, door.y), logic));
+        }
+        rules.doors.sort_by_key(|&(idx, _)| idx);
+        blocks.sort();
+        lasers.sort_by_key(|&(idx, _)| idx);
+        reflectors.sort_by_key(|&(idx, _)| idx);
+        let memory = rules
+            .activables
+            .iter()
+            .map(|&(_, _, behaviour)| match behaviour {
+                ActivationBehaviour::Timed(_) => NEVER,
+                _ => 0,
+            })
+            .collect();
+        let mut state = GameState {
+            player: player.ok_or_else(|| String::from("No player"))?,
+            blocks,
+            lasers,
+            reflectors,
+            memory,
+            opened: 0,
+            state: TurnState::Running,
+        };
+        // As when a turn is played, doors opened at the start only block the map on the second run.
+        state.run(&rules);
+        state.run(&rules);
+        Ok((rules, state))
+    }
+    #[inline]
+    pub fn xy_idx(&self, x: i32, y: i32) -> u16 {
+        (y * self.width + x) as u16
+    }
+    #[inline]
+    pub fn idx_xy(&self, idx: u16) -> (i32, i32) {
+        (idx as i32 % self.width, idx as i32 / self.width)
+    }
+    /// Tile next to idx in the direction, None outside of the map.
+    pub fn neighbour(&self, idx: u16, (dx, dy): (i32, i32)) -> Option<u16> {
+        let (x, y) = self.idx_xy(idx);
+        let (x, y) = (x + dx, y + dy);
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            None
+        } else {
+            Some(self.xy_idx(x, y))
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Piece {
+    Empty,
+    Block,
+    Laser,
+    Reflector(Cardinal),
+    Receptor,
+}
+
+/// Everything that changes during a level, positions are tile indexes.
+/// Pieces are kept sorted so that two states with the same layout are equal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GameState {
+    pub player: u16,
+    pub blocks: Vec<u16>,
+    /// Position and firing direction of the lasers
+    pub lasers: Vec<(u16, Cardinal)>,
+    /// Position and orientation of the reflectors
+    pub reflectors: Vec<(u16, Cardinal)>,
+    /// For each activable of the rules: 1 once a Latching one is latched,
+    /// turns since a Timed one was last triggered while it is active
+    pub memory: Vec<u16>,
+    /// Bit i is set when door i is opened
+    pub opened: u64,
+    pub state: TurnState,
+}
+impl GameState {
+    /// State after the input and the energy it used, None when it has no effect
+    /// or the player is already dead or at the exit.
+    pub fn step(&self, rules: &Rules, input: PlayerInput) -> Option<(GameState, i32)> {
+        if self.state != TurnState::Running {
+            return None;
+        }
+        let mut next = self.clone();
+        let energy = match input {
+            PlayerInput::Move(direction) => next.move_player(rules, direction)?,
+            PlayerInput::Actuate => next.actuate(rules)?,
+        };
+        next.end_turn(rules);
+        Some((next, energy))
+    }
+    pub fn is_door_opened(&self, door: usize) -> bool {
+        self.opened & (1 << door) != 0
+    }
+    fn pieces(&self, rules: &Rules) -> Vec<Piece> {
+        let mut pieces = vec![Piece::Empty; rules.tiles.len()];
+        for &idx in self.blocks.iter() {
+            pieces[idx as usize] = Piece::Block;
+        }
+        for &(idx, _) in self.lasers.iter() {
+            pieces[idx as usize] = Piece::Laser;
+        }
+        for &(idx, orientation) in self.reflectors.iter() {
+            pieces[idx as usize] = Piece::Reflector(orientation);
+        }
+        for &(idx, kind, _) in rules.activables.iter() {
+            if kind == ActivationKind::Laser {
+                pieces[idx as usize] = Piece::Receptor;
+            }
+        }
+        pieces
+    }
+    /// Tiles the player can walk on without dying: neither blocked nor lasered.
+    pub fn walkable(&self, rules: &Rules) -> Vec<bool> {
+        let pieces = self.pieces(rules);
+        let blocked = self.blocked(rules, &pieces);
+        let lasered = self.lasered(rules, &pieces);
+        blocked
+            .iter()
+            .zip(lasered.iter())
+            .map(|(&blocked, &lasered)| !blocked && !lasered)
+            .collect()
+    }
+    /// Tiles the player cannot walk on: walls, pieces and closed doors.
+    fn blocked(&self, rules: &Rules, pieces: &[Piece]) -> Vec<bool> {
+        let mut blocked = rules
+            .tiles
+            .iter()
+            .zip(pieces.iter())
+            .map(|(&tile, &piece)| tile == TileType::Wall || piece != Piece::Empty)
+            .collect::<Vec<_>>();
+        for (door, &(idx, _)) in rules.doors.iter().enumerate() {
+            if !self.is_door_opened(door) {
+                blocked[idx as usize] = true;
+            }
+        }
+        blocked
+    }
+    /// Tiles crossed by a laser beam, including the tile stopping it.
+    fn lasered(&self, rules: &Rules, pieces: &[Piece]) -> Vec<bool> {
+        let mut lasered = vec![false; rules.tiles.len()];
+        for &(start, mut direction) in self.lasers.iter() {
+            let mut idx = start;
+            while let Some(next) = rules.neighbour(idx, direction.get_delta_xy()) {
+                idx = next;
+                lasered[idx as usize] = true;
+                let blocking = rules.tiles[idx as usize] == TileType::Wall
+                    || rules.doors.iter().any(|&(door, _)| door == idx);
+                match pieces[idx as usize] {
+                    Piece::Block | Piece::Laser | Piece::Receptor => break,
+                    _ if blocking => break,
+                    Piece::Reflector(orientation) => direction = direction.reflect(orientation),
+                    Piece::Empty => {}
+                }
+            }
+        }
+        lasered
+    }
+    /// Move the player, pushing the piece in front, returning the energy used.
+    fn move_player(&mut self, rules: &Rules, direction: Cardinal) -> Option<i32> {
+        let (dx, dy) = direction.get_delta_xy();
+        let (x, y) = rules.idx_xy(self.player);
+        let dest = rules.xy_idx(
+            (x + dx).max(0).min(rules.width - 1),
+            (y + dy).max(0).min(rules.height - 1),
+        );
+        let pieces = self.pieces(rules);
+        let blocked = self.blocked(rules, &pieces);
+        if !blocked[dest as usize] {
+            self.player = dest;
+            return Some(0);
+        }
+        let pushed_to = rules
+            .neighbour(dest, (dx, dy))
+            .filter(|&to| !blocked[to as usize])?;
+        match pieces[dest as usize] {
+            Piece::Block => {
+                let block = self.blocks.iter().position(|&b| b == dest).unwrap();
+                self.blocks[block] = pushed_to;
+                self.blocks.sort();
+            }
+            Piece::Laser => {
+                let laser = self.lasers.iter().position(|&(l, _)| l == dest).unwrap();
+                self.lasers[laser].0 = pushed_to;
+                self.lasers.sort_by_key(|&(idx, _)| idx);
+            }
+            Piece::Reflector(_) => {
+                let reflector = self
+                    .reflectors
+                    .iter()
+                    .position(|&(r, _)| r == dest)
+                    .unwrap();
+                self.reflectors[reflector].0 = pushed_to;
+                self.reflectors.sort_by_key(|&(idx, _)| idx);
+            }
+            _ => return None,
+        }
+        self.player = dest;
+        Some(1)
+    }
+    /// Turn the reflectors next to the player, returning the energy used.
+    fn actuate(&mut self, rules: &Rules) -> Option<i32> {
+        let mut energy = 0;
+        for &delta in [(0, 1), (0, -1), (1, 0), (-1, 0)].iter() {
+            if let Some(idx) = rules.neighbour(self.player, delta) {
+                for reflector in self.reflectors.iter_mut().filter(|(r, _)| *r == idx) {
+                    reflector.1 = if reflector.1 == Cardinal::NE {
+                        Cardinal::NW
+                    } else {
+                        Cardinal::NE
+                    };
+                    energy += 1;
+                }
+            }
+        }
+        if energy > 0 {
+            Some(energy)
+        } else {
+            None
+        }
+    }
+    fn end_turn(&mut self, rules: &Rules) {
+        for (memory, &(_, _, behaviour)) in self.memory.iter_mut().zip(rules.activables.iter()) {
+            if let ActivationBehaviour::Timed(_) = behaviour {
+                if *memory != NEVER {
+                    *memory += 1;
+                }
+            }
+        }
+        // The game runs the systems twice per turn, doors opened on the first run
+        // only block the map on the second one.
+        self.run(rules);
+        self.run(rules);
+    }
+    /// Update activables, doors and the player state, as one run of the systems.
+    fn run(&mut self, rules: &Rules) {
+        let pieces = self.pieces(rules);
+        let blocked = self.blocked(rules, &pieces);
+        let lasered = self.lasered(rules, &pieces);
+        let mut active = vec![false; rules.activables.len()];
+        for (i, &(idx, kind, behaviour)) in rules.activables.iter().enumerate() {
+            let triggered = match kind {
+                ActivationKind::Weight => blocked[idx as usize],
+                ActivationKind::Laser => lasered[idx as usize],
+            };
+            let memory = &mut self.memory[i];
+            active[i] = match behaviour {
+                ActivationBehaviour::Momentary => triggered,
+                ActivationBehaviour::Latching => {
+                    if triggered {
+                        *memory = 1;
+                    }
+                    *memory == 1
+                }
+                ActivationBehaviour::Timed(turns) => {
+                    if triggered {
+                        *memory = 0;
+                    }
+                    if *memory != NEVER && *memory as i32 > turns {
+                        *memory = NEVER;
+                    }
+                    *memory != NEVER
+                }
+            };
+        }
+        self.opened = 0;
+        for (door, (_, logic)) in rules.doors.iter().enumerate() {
+            if logic.eval(&|&activable: &usize| active[activable]) {
+                self.opened |= 1 << door;
+            }
+        }
+        if lasered[self.player as usize] {
+            self.state = TurnState::PlayerDead;
+        } else if rules.tiles[self.player as usize] == TileType::Exit {
+            self.state = TurnState::PlayerAtExit;
+        }
+    }
+}
@@ -1,6 +1,32 @@
-use crate::{map, turn_history::TurnsHistory, RunState, State, TERM_WIDTH};
+use crate::{
+    assets,
+    components::Cardinal,
+    level::{self, LevelError, LevelInfo},
+    map,
+    pack::{LevelPacks, LevelRef},
+    player::{try_input, PlayerInput},
+    progress::Progress,
+    turn_history::TurnsHistory,
+    RunState, State, TERM_WIDTH,
+};
 use bracket_lib::prelude::*;
 use legion::prelude::Resources;
+use std::fs;
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Outcome of the last operation shown to the player, such as saving a snapshot.
+#[derive(Clone, Debug, Default)]
+pub struct UiMessage(pub String);
+
+/// Draw the message line, wrapped on the lines above the bottom of the screen.
+pub fn draw_message(rsrc: &Resources, ctx: &mut BTerm) {
+    if let Some(message) = rsrc.get::<UiMessage>() {
+        for (i, txt) in wrap_text(&message.0).iter().take(2).enumerate() {
+            ctx.print_color(1, 26 + i as i32, RGB::named(ORANGE), RGB::named(BLACK), txt);
+        }
+    }
+}
 
 pub fn draw_dead(_gs: &State, ctx: &mut BTerm) {
     let txt = "You died !";
@@ -30,7 +56,7 @@ pub fn draw_dead(_gs: &State, ctx: &mut BTerm) {
         txt,
     );
 }
-pub fn draw_level_solved(_gs: &State, ctx: &mut BTerm) {
+pub fn draw_level_solved(gs: &State, ctx: &mut BTerm) {
     let txt = "Level solved !";
     let start_x = (TERM_WIDTH - (txt.len() as i32 + 4)) / 2;
     let start_y = 5;
@@ -49,15 +75,184 @@ pub fn draw_level_solved(_gs: &State, ctx: &mut BTerm) {
         RGB::named(BLACK),
         txt,
     );
-    let txt = "<ENTER to go to next level>";
+    let info = gs.rsrc.get::<LevelInfo>().unwrap();
+    let turn_history = gs.rsrc.get::<TurnsHistory>().unwrap();
+    let mut y = start_y + 4;
+    for (name, value, par) in [
+        ("Steps ", turn_history.steps, info.par_steps),
+        ("Energy", turn_history.energy_used, info.par_energy),
+    ]
+    .iter()
+    {
+        if let Some(par) = par {
+            let fg = if value <= par {
+                RGB::named(GREEN)
+            } else {
+                RGB::named(ORANGE)
+            };
+            let txt = format!("{}: {} (par {})", name, value, par);
+            ctx.print_color(
+                (TERM_WIDTH - txt.len() as i32) / 2,
+                y,
+                fg,
+                RGB::named(BLACK),
+                txt,
+            );
+            y += 1;
+        }
+    }
+    if y > start_y + 4 {
+        y += 1;
+    }
+    for txt in ["<ENTER to go to next level>", "<W to watch the replay>"].iter() {
+        ctx.print_color(
+            (TERM_WIDTH - txt.len() as i32) / 2,
+            y,
+            RGB::named(WHITE),
+            RGB::named(BLACK),
+            txt,
+        );
+        y += 1;
+    }
+}
+pub fn draw_level_error(rsrc: &Resources, ctx: &mut BTerm) {
+    let error = rsrc.get::<LevelError>().unwrap();
+    let txt = "Invalid level !";
+    let start_x = (TERM_WIDTH - (txt.len() as i32 + 4)) / 2;
+    let start_y = 5;
+    ctx.draw_box(
+        start_x,
+        start_y,
+        txt.len() + 4 - 1,
+        2,
+        RGB::named(RED1),
+        RGB::named(BLACK),
+    );
     ctx.print_color(
         (TERM_WIDTH - txt.len() as i32) / 2,
-        start_y + 4,
+        start_y + 1,
+        RGB::named(RED1),
+        RGB::named(BLACK),
+        txt,
+    );
+    let location = if error.line == 0 {
+        error.file.clone()
+    } else {
+        format!("{}:{}:{}", error.file, error.line, error.column)
+    };
+    let mut y = start_y + 4;
+    for txt in wrap_text(&location)
+        .iter()
+        .chain(wrap_text(&error.message()).iter())
+    {
+        ctx.print_color(1, y, RGB::named(WHITE), RGB::named(BLACK), txt);
+        y += 1;
+    }
+    let txt = "<ENTER to go to the menu>";
+    ctx.print_color(
+        (TERM_WIDTH - txt.len() as i32) / 2,
+        y + 1,
         RGB::named(WHITE),
         RGB::named(BLACK),
         txt,
     );
 }
+fn par_text(par: Option<i32>) -> String {
+    par.map_or(String::from("-"), |x| x.to_string())
+}
+/// Split a text in lines fitting in the terminal width, keeping a 1 char margin on each side.
+fn wrap_text(txt: &str) -> Vec<String> {
+    let max_len = (TERM_WIDTH - 2) as usize;
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in txt.split(' ') {
+        if !line.is_empty() && line.len() + 1 + word.len() > max_len {
+            lines.push(line);
+            line = String::new();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+        while line.len() > max_len {
+            let rest = line.split_off(max_len);
+            lines.push(line);
+            line = rest;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Save the current position as a new level in the snapshots directory of the user data directory.
+fn save_snapshot(gs: &State) -> UiMessage {
+    let dir = match assets::user_data_dir() {
+        Some(dir) => dir.join(SNAPSHOTS_DIR),
+        None => return UiMessage(String::from("Cannot save snapshot: no user data directory")),
+    };
+    let map = gs.rsrc.get::<map::Map>().unwrap();
+    let info = gs.rsrc.get::<LevelInfo>().unwrap();
+    let steps = gs.rsrc.get::<TurnsHistory>().unwrap().steps;
+    let content = level::save_level_with_info(&gs.ecs, &map, &info);
+    let file = dir.join(format!(
+        "snapshot_{}_{}.txt",
+        map.level.map_or(0, |level| level.index + 1),
+        steps
+    ));
+    UiMessage(
+        match fs::create_dir_all(&dir).and_then(|_| fs::write(&file, content)) {
+            Ok(()) => format!("Snapshot saved to {}", file.display()),
+            Err(err) => format!("Cannot save snapshot {}: {}", file.display(), err),
+        },
+    )
+}
+
+pub fn game_turn_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
+    let input;
+    match ctx.key {
+        None => {
+            return RunState::GameAwaitingInput;
+        }
+        Some(key) => match key {
+            VirtualKeyCode::Left => input = PlayerInput::Move(Cardinal::W),
+            VirtualKeyCode::Right => input = PlayerInput::Move(Cardinal::E),
+            VirtualKeyCode::Up => input = PlayerInput::Move(Cardinal::N),
+            VirtualKeyCode::Down => input = PlayerInput::Move(Cardinal::S),
+            VirtualKeyCode::Space => input = PlayerInput::Actuate,
+            VirtualKeyCode::Back => {
+                let mut turn_history = gs.rsrc.get_mut::<TurnsHistory>().unwrap();
+                turn_history.undo_last_turn(&mut gs.ecs);
+                return RunState::GameTurn;
+            }
+            VirtualKeyCode::Y => {
+                let mut turn_history = gs.rsrc.get_mut::<TurnsHistory>().unwrap();
+                turn_history.redo_turn(&mut gs.ecs);
+                return RunState::GameTurn;
+            }
+            VirtualKeyCode::F2 => {
+                let message = save_snapshot(gs);
+                gs.rsrc.insert(message);
+                return RunState::GameDraw;
+            }
+            VirtualKeyCode::Escape => {
+                return RunState::MainMenu {
+                    menu_selection: MainMenuSelection::Continue,
+                }
+            }
+            _ => {
+                return RunState::GameAwaitingInput;
+            }
+        },
+    }
+    let actions = try_input(input, &mut gs.ecs, &mut gs.rsrc);
+    if actions.len() > 0 {
+        let mut turn_history = gs.rsrc.get_mut::<TurnsHistory>().unwrap();
+        turn_history.play_turn(&mut gs.ecs, Some(input), actions);
+    }
+    RunState::GameTurn
+}
 
 pub fn game_end_dead_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
     match ctx.key {
@@ -68,7 +263,10 @@ pub fn game_end_dead_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
             },
             VirtualKeyCode::R => {
                 let map = gs.rsrc.get::<map::Map>().unwrap();
-                RunState::LoadLevel(map.level)
+                match map.level {
+                    Some(level) => RunState::LoadLevel(level),
+                    None => RunState::GameDraw,
+                }
             }
             VirtualKeyCode::Back => {
                 let mut turn_history = gs.rsrc.get_mut::<TurnsHistory>().unwrap();
@@ -79,25 +277,89 @@ pub fn game_end_dead_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
         },
     }
 }
-pub fn game_level_end_input(gs: &State, ctx: &mut BTerm) -> RunState {
+pub fn game_level_end_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
     match ctx.key {
         None => RunState::GameDraw,
         Some(key) => match key {
             VirtualKeyCode::Return => {
                 let map = gs.rsrc.get::<map::Map>().unwrap();
-                RunState::LoadLevel(map.level + 1)
+                let packs = gs.rsrc.get::<LevelPacks>().unwrap();
+                match map.level.and_then(|level| packs.next(level)) {
+                    Some(level) => RunState::LoadLevel(level),
+                    None => RunState::MainMenu {
+                        menu_selection: MainMenuSelection::NewPlayerGame,
+                    },
+                }
             }
+            VirtualKeyCode::W => gs.watch_replay(),
             _ => RunState::GameDraw,
         },
     }
 }
 
+pub fn level_error_input(ctx: &mut BTerm) -> RunState {
+    match ctx.key {
+        Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::Escape) => RunState::MainMenu {
+            menu_selection: MainMenuSelection::NewPlayerGame,
+        },
+        _ => RunState::LevelError,
+    }
+}
+
 pub fn draw_ui(rsrc: &Resources, ctx: &mut BTerm) {
     let map = rsrc.get::<map::Map>().unwrap();
     let turn_history = rsrc.get::<TurnsHistory>().unwrap();
-    ctx.print(1, 1, format!("Level : {}", map.level));
-    ctx.print(1, 2, format!("Steps : {}", turn_history.steps));
+    let packs = rsrc.get::<LevelPacks>().unwrap();
+    let info = rsrc.get::<LevelInfo>().unwrap();
+    let progress = rsrc.get::<Progress>().unwrap();
+    if let Some(level) = map.level {
+        ctx.print(1, 1, format!("Level : {}", level));
+        let title = info
+            .title
+            .as_ref()
+            .or_else(|| packs.get(level).map(|pack_level| &pack_level.title));
+        if let Some(title) = title {
+            ctx.print_color_centered(9, RGB::named(YELLOW), RGB::named(BLACK), title);
+        }
+    }
+    if turn_history.redo.is_empty() {
+        ctx.print(1, 2, format!("Steps : {}", turn_history.steps));
+    } else {
+        ctx.print(
+            1,
+            2,
+            format!(
+                "Steps : {}/{}",
+                turn_history.steps,
+                turn_history.steps + turn_history.redo.len() as i32
+            ),
+        );
+    }
     ctx.print(1, 3, format!("Energy: {}", turn_history.energy_used));
+    if info.par_steps.is_some() || info.par_energy.is_some() {
+        ctx.print(
+            1,
+            4,
+            format!(
+                "Par   : {}/{}",
+                par_text(info.par_steps),
+                par_text(info.par_energy)
+            ),
+        );
+    }
+    if let Some(record) = map.level.and_then(|level| progress.record(&packs, level)) {
+        ctx.print(
+            1,
+            5,
+            format!("Best  : {}/{}", record.best_steps, record.best_energy),
+        );
+    }
+    if let Some(hint) = info.hint.as_ref() {
+        for (i, txt) in wrap_text(hint).iter().enumerate() {
+            ctx.print_color(1, 24 + i as i32, RGB::named(GRAY), RGB::named(BLACK), txt);
+        }
+    }
+    draw_message(rsrc, ctx);
     ctx.print(20, 29, format!("Fps: {:.2}", ctx.fps));
 }
 
@@ -105,14 +367,18 @@ pub fn draw_ui(rsrc: &Resources, ctx: &mut BTerm) {
 pub enum MainMenuSelection {
     NewPlayerGame = 0,
     NewAiGame = 1,
-    Continue = 2,
-    Quit = 3,
+    LevelSelect = 2,
+    Editor = 3,
+    Continue = 4,
+    Quit = 5,
 }
 impl MainMenuSelection {
     fn get_name(&self) -> String {
         String::from(match self {
             MainMenuSelection::NewPlayerGame => "New Game",
             MainMenuSelection::NewAiGame => "New AI Game",
+            MainMenuSelection::LevelSelect => "Select Level",
+            MainMenuSelection::Editor => "Level Editor",
             MainMenuSelection::Continue => "Continue",
             MainMenuSelection::Quit => "Quit",
         })
@@ -143,6 +409,8 @@ pub fn main_menu(
         vec![
             MainMenuSelection::NewPlayerGame,
             MainMenuSelection::NewAiGame,
+            MainMenuSelection::LevelSelect,
+            MainMenuSelection::Editor,
             MainMenuSelection::Continue,
             MainMenuSelection::Quit,
         ]
@@ -150,6 +418,8 @@ pub fn main_menu(
         vec![
             MainMenuSelection::NewPlayerGame,
             MainMenuSelection::NewAiGame,
+            MainMenuSelection::LevelSelect,
+            MainMenuSelection::Editor,
             MainMenuSelection::Quit,
         ]
     };
@@ -193,3 +463,99 @@ pub fn main_menu(
         },
     }
 }
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum LevelSelectResult {
+    NoSelection { selected: LevelRef },
+    Selected { selected: LevelRef },
+    Cancel,
+}
+/// Number of levels listed at once, the list scrolls to keep the selected one visible.
+const LEVEL_SELECT_ROWS: usize = 8;
+/// List the levels of the pack of the selected level, the preview is drawn below by the caller.
+/// Up/Down choose a level, Left/Right change pack, locked levels cannot be selected.
+pub fn level_select(rsrc: &Resources, ctx: &mut BTerm, selection: LevelRef) -> LevelSelectResult {
+    let packs = rsrc.get::<LevelPacks>().unwrap();
+    let progress = rsrc.get::<Progress>().unwrap();
+    let pack = &packs.packs[selection.pack];
+    ctx.print_color_centered(
+        1,
+        RGB::named(YELLOW),
+        RGB::named(BLACK),
+        format!("< {} >", pack.title),
+    );
+    let first = selection
+        .index
+        .saturating_sub(LEVEL_SELECT_ROWS / 2)
+        .min(pack.levels.len().saturating_sub(LEVEL_SELECT_ROWS));
+    for (row, index) in (first..pack.levels.len().min(first + LEVEL_SELECT_ROWS)).enumerate() {
+        let level = LevelRef {
+            pack: selection.pack,
+            index,
+        };
+        let record = progress.record(&packs, level);
+        let fg = if !progress.is_unlocked(&packs, level) {
+            RGB::named(GRAY)
+        } else if record.is_some() {
+            RGB::named(GREEN)
+        } else {
+            RGB::named(WHITE)
+        };
+        let bg = if index == selection.index {
+            RGB::named(DARK_BLUE)
+        } else {
+            RGB::named(BLACK)
+        };
+        let mut title = format!("{:>3} {}", index + 1, pack.levels[index].title);
+        title.truncate(30);
+        let best = record.map_or(String::new(), |record| {
+            format!("{}/{}", record.best_steps, record.best_energy)
+        });
+        ctx.print_color(
+            1,
+            3 + row as i32,
+            fg,
+            bg,
+            format!("{:<30}{:>8}", title, best),
+        );
+    }
+    let n_levels = pack.levels.len();
+    let n_packs = packs.packs.len();
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => LevelSelectResult::Cancel,
+        Some(VirtualKeyCode::Up) => LevelSelectResult::NoSelection {
+            selected: LevelRef {
+                pack: selection.pack,
+                index: (selection.index + n_levels - 1) % n_levels,
+            },
+        },
+        Some(VirtualKeyCode::Down) => LevelSelectResult::NoSelection {
+            selected: LevelRef {
+                pack: selection.pack,
+                index: (selection.index + 1) % n_levels,
+            },
+        },
+        Some(VirtualKeyCode::Left) => LevelSelectResult::NoSelection {
+            selected: first_level_of(&packs, (selection.pack + n_packs - 1) % n_packs),
+        },
+        Some(VirtualKeyCode::Right) => LevelSelectResult::NoSelection {
+            selected: first_level_of(&packs, (selection.pack + 1) % n_packs),
+        },
+        Some(VirtualKeyCode::Return) if progress.is_unlocked(&packs, selection) => {
+            LevelSelectResult::Selected {
+                selected: selection,
+            }
+        }
+        _ => LevelSelectResult::NoSelection {
+            selected: selection,
+        },
+    }
+}
+/// First level of the pack, skipping packs without levels.
+fn first_level_of(packs: &LevelPacks, pack: usize) -> LevelRef {
+    let pack = (0..packs.packs.len())
+        .map(|i| (pack + i) % packs.packs.len())
+        .find(|&i| !packs.packs[i].levels.is_empty())
+        .unwrap_or(pack);
+    LevelRef { pack, index: 0 }
+}
@@ -0,0 +1,340 @@
+//! Legend section of a level file, following the grid.
+//! Each line is `label: definition`, for example `A: door(3,5) <- plate(2,2), receptor(7,1)`.
+//! Coordinates are the 0-based column and row of the tile in the grid.
+//! A comma separated list of activators means all of them must be active, and can be combined
+//! with `all(..)`, `any(..)`, `one(..)` (exactly one) and `not(..)`,
+//! for example `B: door(4,0) <- any(plate(2,2), not(plate(5,6)))`.
+//! `plate(x,y)` and `exit(x,y)` put a plate or an exit under the entity shown in the grid.
+//! Plates can be made latching with `latch(x,y)`, or stay active n turns with `timer(x,y,n)`.
+use crate::components::Logic;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activator {
+    Plate(i32, i32),
+    Receptor(i32, i32),
+}
+impl Activator {
+    pub fn position(&self) -> (i32, i32) {
+        match *self {
+            Activator::Plate(x, y) | Activator::Receptor(x, y) => (x, y),
+        }
+    }
+}
+impl fmt::Display for Activator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Activator::Plate(x, y) => write!(f, "plate({},{})", x, y),
+            Activator::Receptor(x, y) => write!(f, "receptor({},{})", x, y),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LegendItem {
+    /// Door at x,y opened when its logic is true.
+    Door {
+        x: i32,
+        y: i32,
+        logic: Logic<Activator>,
+    },
+    /// Plate at x,y, under the entity shown in the grid.
+    Plate { x: i32, y: i32 },
+    /// Exit at x,y, under the entity shown in the grid.
+    Exit { x: i32, y: i32 },
+    /// Plate at x,y stays active once pressed.
+    Latch { x: i32, y: i32 },
+    /// Plate at x,y stays active for turns after being released.
+    Timer { x: i32, y: i32, turns: i32 },
+}
+impl LegendItem {
+    pub fn position(&self) -> (i32, i32) {
+        match *self {
+            LegendItem::Door { x, y, .. }
+            | LegendItem::Plate { x, y }
+            | LegendItem::Exit { x, y }
+            | LegendItem::Latch { x, y }
+            | LegendItem::Timer { x, y, .. } => (x, y),
+        }
+    }
+}
+/// Writes the definition in the syntax read by parse_legend_line.
+impl fmt::Display for LegendItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LegendItem::Door { x, y, logic } => {
+                write!(f, "door({},{})", x, y)?;
+                match logic {
+                    Logic::All(logics) if logics.is_empty() => Ok(()),
+                    Logic::All(logics) if logics.len() > 1 => {
+                        write!(f, " <- {}", format_logic_list(logics))
+                    }
+                    logic => write!(f, " <- {}", format_logic(logic)),
+                }
+            }
+            LegendItem::Plate { x, y } => write!(f, "plate({},{})", x, y),
+            LegendItem::Exit { x, y } => write!(f, "exit({},{})", x, y),
+            LegendItem::Latch { x, y } => write!(f, "latch({},{})", x, y),
+            LegendItem::Timer { x, y, turns } => write!(f, "timer({},{},{})", x, y, turns),
+        }
+    }
+}
+fn format_logic(logic: &Logic<Activator>) -> String {
+    match logic {
+        Logic::Is(activator) => activator.to_string(),
+        Logic::All(logics) => format!("all({})", format_logic_list(logics)),
+        Logic::Any(logics) => format!("any({})", format_logic_list(logics)),
+        Logic::One(logics) => format!("one({})", format_logic_list(logics)),
+        Logic::Not(logic) => format!("not({})", format_logic(logic)),
+    }
+}
+fn format_logic_list(logics: &[Logic<Activator>]) -> String {
+    logics
+        .iter()
+        .map(format_logic)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Error in a legend line, column is 1-based.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LegendError {
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i32),
+    Open,
+    Close,
+    Comma,
+    Arrow,
+    End,
+}
+
+/// Parse one legend line, returning its label and definition.
+pub fn parse_legend_line(line: &str) -> Result<(String, LegendItem), LegendError> {
+    let pos = line.find(':').ok_or_else(|| LegendError {
+        column: 1,
+        message: String::from("Expected 'label: definition'"),
+    })?;
+    let label = line[..pos].trim();
+    if label.is_empty() || !label.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(LegendError {
+            column: 1,
+            message: format!("Invalid label '{}'", label),
+        });
+    }
+    let mut parser = Parser {
+        tokens: tokenize(&line[pos + 1..], pos + 2)?,
+        pos: 0,
+    };
+    let item = parser.item()?;
+    parser.expect(Token::End, "end of line")?;
+    Ok((String::from(label), item))
+}
+
+fn tokenize(txt: &str, first_column: usize) -> Result<Vec<(usize, Token)>, LegendError> {
+    let mut tokens = vec![];
+    let chars = txt.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = first_column + i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '<' if chars.get(i + 1) == Some(&'-') => {
+                i += 1;
+                Token::Arrow
+            }
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i + 1 < chars.len() && chars[i + 1].is_ascii_digit() {
+                    i += 1;
+                }
+                let number = chars[start..=i].iter().collect::<String>();
+                Token::Number(number.parse().map_err(|_| LegendError {
+                    column,
+                    message: format!("Invalid number '{}'", number),
+                })?)
+            }
+            c if c.is_alphabetic() => {
+                let start = i;
+                while i + 1 < chars.len() && (chars[i + 1].is_alphanumeric() || chars[i + 1] == '_')
+                {
+                    i += 1;
+                }
+                Token::Ident(chars[start..=i].iter().collect())
+            }
+            c => {
+                return Err(LegendError {
+                    column,
+                    message: format!("Unexpected character '{}'", c),
+                })
+            }
+        };
+        tokens.push((column, token));
+        i += 1;
+    }
+    tokens.push((first_column + chars.len(), Token::End));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+    fn column(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+    fn error(&self, expected: &str) -> LegendError {
+        LegendError {
+            column: self.column(),
+            message: format!("Expected {}", expected),
+        }
+    }
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), LegendError> {
+        if *self.peek() == token {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+    fn ident(&mut self, expected: &str) -> Result<String, LegendError> {
+        match self.peek().clone() {
+            Token::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+    fn number(&mut self) -> Result<i32, LegendError> {
+        match *self.peek() {
+            Token::Number(x) => {
+                self.next();
+                Ok(x)
+            }
+            _ => Err(self.error("a number")),
+        }
+    }
+    /// Parse `(x,y)`
+    fn coordinates(&mut self) -> Result<(i32, i32), LegendError> {
+        self.expect(Token::Open, "'('")?;
+        let x = self.number()?;
+        self.expect(Token::Comma, "','")?;
+        let y = self.number()?;
+        self.expect(Token::Close, "')'")?;
+        Ok((x, y))
+    }
+    fn item(&mut self) -> Result<LegendItem, LegendError> {
+        let column = self.column();
+        match self.ident("a definition")?.as_str() {
+            "door" => {
+                let (x, y) = self.coordinates()?;
+                let mut logics = vec![];
+                if *self.peek() == Token::Arrow {
+                    self.next();
+                    logics = self.logic_list()?;
+                }
+                let logic = if logics.len() == 1 {
+                    logics.pop().unwrap()
+                } else {
+                    Logic::All(logics)
+                };
+                Ok(LegendItem::Door { x, y, logic })
+            }
+            "plate" => {
+                let (x, y) = self.coordinates()?;
+                Ok(LegendItem::Plate { x, y })
+            }
+            "exit" => {
+                let (x, y) = self.coordinates()?;
+                Ok(LegendItem::Exit { x, y })
+            }
+            "latch" => {
+                let (x, y) = self.coordinates()?;
+                Ok(LegendItem::Latch { x, y })
+            }
+            "timer" => {
+                self.expect(Token::Open, "'('")?;
+                let x = self.number()?;
+                self.expect(Token::Comma, "','")?;
+                let y = self.number()?;
+                self.expect(Token::Comma, "','")?;
+                let turns = self.number()?;
+                self.expect(Token::Close, "')'")?;
+                Ok(LegendItem::Timer { x, y, turns })
+            }
+            name => Err(LegendError {
+                column,
+                message: format!("Unknown definition '{}'", name),
+            }),
+        }
+    }
+    /// Parse a non empty comma separated list of logic expressions.
+    fn logic_list(&mut self) -> Result<Vec<Logic<Activator>>, LegendError> {
+        let mut logics = vec![self.logic()?];
+        while *self.peek() == Token::Comma {
+            self.next();
+            logics.push(self.logic()?);
+        }
+        Ok(logics)
+    }
+    fn logic(&mut self) -> Result<Logic<Activator>, LegendError> {
+        let column = self.column();
+        match self.ident("an activator or a logic operator")?.as_str() {
+            "plate" => {
+                let (x, y) = self.coordinates()?;
+                Ok(Logic::Is(Activator::Plate(x, y)))
+            }
+            "receptor" => {
+                let (x, y) = self.coordinates()?;
+                Ok(Logic::Is(Activator::Receptor(x, y)))
+            }
+            operator @ "all" | operator @ "any" | operator @ "one" | operator @ "not" => {
+                self.expect(Token::Open, "'('")?;
+                let mut logics = self.logic_list()?;
+                self.expect(Token::Close, "')'")?;
+                Ok(match operator {
+                    "all" => Logic::All(logics),
+                    "any" => Logic::Any(logics),
+                    "one" => Logic::One(logics),
+                    _ => {
+                        if logics.len() != 1 {
+                            return Err(LegendError {
+                                column,
+                                message: String::from("not(..) takes a single argument"),
+                            });
+                        }
+                        Logic::Not(Box::new(logics.pop().unwrap()))
+                    }
+                })
+            }
+            name => Err(LegendError {
+                column,
+                message: format!("Unknown activator or logic operator '{}'", name),
+            }),
+        }
+    }
+}
@@ -1,221 +1,898 @@
 use super::State;
-use crate::glyphs::*;
 use crate::{
+    assets,
     components::{
-        Activable, ActivationKind, Actuator, Block, BlocksLaser, BlocksTile, Cardinal, Door, Laser,
-        Movable, Player, Position, ReflectsLaser, Renderable,
+        Activable, ActivationBehaviour, ActivationKind, Actuator, Block, BlocksLaser, BlocksTile,
+        Cardinal, Door, Laser, Logic, Movable, Player, Position, ReflectsLaser,
     },
+    legend::{parse_legend_line, Activator, LegendItem},
     map,
+    pack::{LevelPacks, LevelRef},
     turn_history::TurnsHistory,
 };
-use bracket_lib::prelude::*;
 use legion::prelude::*;
 use map::TileType;
+use std::fmt;
 use std::fs;
 
-pub fn load_level(gs: &mut State, level: u64) {
-    match level {
-        x if x <= 4 => load_level_from_file(level, &format!("resources/level_00{}.txt", level), gs),
-        x if x >= 10001 && x <= 10006 => load_level_from_file(
-            level,
-            &format!("resources/ai_test_{}.txt", level - 10000),
-            gs,
-        ),
-        _ => load_level_from_file(level, "resources/level_end.txt", gs),
+/// Name of the door put on the exit when the legend does not declare any door.
+const EXIT_DOOR: &str = "exit";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LevelEntity {
+    Player,
+    Block,
+    WeightPlate,
+    LaserReceptor,
+    Laser(Cardinal),
+    Reflector(Cardinal),
+}
+
+/// Optional metadata given in the header of a level file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub par_steps: Option<i32>,
+    pub par_energy: Option<i32>,
+    pub hint: Option<String>,
+}
+
+/// Parsed content of a level file, before anything is spawned in the World.
+pub struct LevelDescription {
+    pub info: LevelInfo,
+    pub width: i32,
+    pub height: i32,
+    pub tiles: Vec<TileType>,
+    pub entities: Vec<(i32, i32, LevelEntity)>,
+    pub doors: Vec<DoorDescription>,
+    /// Plates that are not Momentary
+    pub behaviours: Vec<(i32, i32, ActivationBehaviour)>,
+    /// content_hash of the level file
+    pub hash: u64,
+}
+
+/// Hash of the file of the current level, telling apart versions of a level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelHash(pub u64);
+
+/// A door and the logic over plates and receptors opening it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DoorDescription {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub logic: Logic<Activator>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelErrorKind {
+    Io(String),
+    Empty,
+    UnknownGlyph(char),
+    RaggedRow { expected: usize, found: usize },
+    MissingPlayer,
+    MultiplePlayers,
+    MissingExit,
+    ExitWithoutDoor(i32, i32),
+    UnclosedBorder(char),
+    InvalidManifest(String),
+    MissingPack(String),
+    MissingLevel,
+    UnknownHeaderKey(String),
+    InvalidHeaderValue(String),
+    InvalidLegend(String),
+}
+/// Error found while loading a level.
+/// line and column are 1-based, 0 when the problem is not tied to a position in the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub kind: LevelErrorKind,
+}
+impl LevelError {
+    fn new(file: &str, line: usize, column: usize, kind: LevelErrorKind) -> LevelError {
+        LevelError {
+            file: String::from(file),
+            line,
+            column,
+            kind,
+        }
+    }
+    pub fn message(&self) -> String {
+        match &self.kind {
+            LevelErrorKind::Io(err) => format!("Cannot read file: {}", err),
+            LevelErrorKind::Empty => String::from("Level is empty"),
+            LevelErrorKind::UnknownGlyph(c) => format!("Unknown glyph '{}'", c),
+            LevelErrorKind::RaggedRow { expected, found } => format!(
+                "Row has {} columns, expected {} like the first row",
+                found, expected
+            ),
+            LevelErrorKind::MissingPlayer => String::from("No player '@' in level"),
+            LevelErrorKind::MultiplePlayers => String::from("Several players '@' in level"),
+            LevelErrorKind::MissingExit => String::from("No exit 'E' in level"),
+            LevelErrorKind::ExitWithoutDoor(x, y) => format!(
+                "Exit ({},{}) has no door, the legend must wire a door on every exit",
+                x, y
+            ),
+            LevelErrorKind::UnclosedBorder(c) => {
+                format!("Border must be wall '#' or exit 'E', found '{}'", c)
+            }
+            LevelErrorKind::InvalidManifest(err) => format!("Invalid pack manifest: {}", err),
+            LevelErrorKind::MissingPack(name) => format!("No level pack named '{}'", name),
+            LevelErrorKind::MissingLevel => String::from("Level not found in its pack"),
+            LevelErrorKind::UnknownHeaderKey(key) => format!("Unknown header key '{}'", key),
+            LevelErrorKind::InvalidHeaderValue(key) => {
+                format!("Header '{}' expects a positive number", key)
+            }
+            LevelErrorKind::InvalidLegend(message) => format!("Invalid legend: {}", message),
+        }
+    }
+}
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message())
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file,
+                self.line,
+                self.column,
+                self.message()
+            )
+        }
     }
 }
 
-fn load_level_from_file(level: u64, file: &str, gs: &mut State) {
-    let content = fs::read_to_string(file).unwrap();
-    let lines = content.trim().split("\n").collect::<Vec<_>>();
+pub fn load_level(gs: &mut State, level: LevelRef) -> Result<(), LevelError> {
+    let file = gs
+        .rsrc
+        .get::<LevelPacks>()
+        .unwrap()
+        .level_path(level)
+        .ok_or_else(|| LevelError::new(assets::PACKS_DIR, 0, 0, LevelErrorKind::MissingLevel))?;
+    let description = read_level(&file)?;
+    build_level(&mut gs.ecs, &mut gs.rsrc, Some(level), &description);
+    Ok(())
+}
+
+/// Read and parse a level file of the assets.
+pub fn read_level(file: &str) -> Result<LevelDescription, LevelError> {
+    let content = assets::read(file)
+        .map_err(|err| LevelError::new(file, 0, 0, LevelErrorKind::Io(err.to_string())))?;
+    parse_level(file, &content)
+}
+
+/// Read and parse a level file from disk, outside of the assets.
+pub fn load_level_from_file(file: &str) -> Result<LevelDescription, LevelError> {
+    let content = fs::read_to_string(file)
+        .map_err(|err| LevelError::new(file, 0, 0, LevelErrorKind::Io(err.to_string())))?;
+    parse_level(file, &content)
+}
+
+/// Parse the text format of a level.
+/// file is only used to report errors.
+/// The grid can be preceded by a header of `key: value` lines,
+/// and followed by a legend wiring doors to their activators.
+/// Without any door in the legend, the exit gets a door wired to every plate and receptor,
+/// otherwise the legend must wire a door on every exit.
+pub fn parse_level(file: &str, content: &str) -> Result<LevelDescription, LevelError> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line))
+        .peekable();
+    let mut info = LevelInfo::default();
+    while let Some(&(line_nb, line)) = lines.peek() {
+        if !line.trim().is_empty() {
+            match line.find(':') {
+                Some(pos) => {
+                    parse_header(file, line_nb, &line[..pos], &line[pos + 1..], &mut info)?
+                }
+                None => break,
+            }
+        }
+        lines.next();
+    }
+    let mut lines = lines.collect::<Vec<_>>();
+    let grid_end = lines
+        .iter()
+        .position(|(_, line)| line.trim().is_empty() || line.contains(':'))
+        .unwrap_or_else(|| lines.len());
+    if grid_end == 0 {
+        return Err(LevelError::new(file, 0, 0, LevelErrorKind::Empty));
+    }
+    let legend_lines = lines.split_off(grid_end);
     let height = lines.len();
-    let width = lines[0].len();
-    gs.ecs.delete_all();
-    let mut map = map::Map::new(level, width as i32, height as i32);
-    let mut activations = vec![];
-    let mut exit = (0, 0);
-    for y in 0..height {
-        for (x, c) in lines[y].chars().enumerate() {
+    let width = lines[0].1.chars().count();
+    let mut tiles = vec![TileType::Floor; width * height];
+    let mut entities = vec![];
+    let mut player = None;
+    // Border tiles that are not walls must be exits, which can also be declared in the legend.
+    let mut open_borders = vec![];
+    for (y, &(line_nb, line)) in lines.iter().enumerate() {
+        let found = line.chars().count();
+        if found != width {
+            return Err(LevelError::new(
+                file,
+                line_nb,
+                width.min(found) + 1,
+                LevelErrorKind::RaggedRow {
+                    expected: width,
+                    found,
+                },
+            ));
+        }
+        for (x, c) in line.chars().enumerate() {
+            let column = x + 1;
+            let on_border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+            let idx = y * width + x;
+            if on_border && c != '#' && c != 'E' {
+                open_borders.push((
+                    idx,
+                    LevelError::new(file, line_nb, column, LevelErrorKind::UnclosedBorder(c)),
+                ));
+            }
             let x = x as i32;
             let y = y as i32;
             match c {
-                '#' => map.set_tiletype(x, y, TileType::Wall),
-                '.' => map.set_tiletype(x, y, TileType::Floor),
-                ' ' => map.set_tiletype(x, y, TileType::Floor),
+                '#' => tiles[idx] = TileType::Wall,
+                '.' | ' ' => {}
                 '*' => {
-                    map.set_tiletype(x, y, TileType::Floor);
-                    activations.push(spawn_weight_plate(gs, x, y));
-                    spawn_block(gs, x, y);
-                }
-                'E' => {
-                    map.set_tiletype(x, y, TileType::Exit);
-                    exit = (x, y);
-                }
-                'e' => {
-                    spawn_laser(gs, x, y, Cardinal::E);
+                    entities.push((x, y, LevelEntity::WeightPlate));
+                    entities.push((x, y, LevelEntity::Block));
                 }
-                'n' => {
-                    spawn_laser(gs, x, y, Cardinal::N);
-                }
-                's' => {
-                    spawn_laser(gs, x, y, Cardinal::S);
+                'E' => tiles[idx] = TileType::Exit,
+                'e' => entities.push((x, y, LevelEntity::Laser(Cardinal::E))),
+                'n' => entities.push((x, y, LevelEntity::Laser(Cardinal::N))),
+                's' => entities.push((x, y, LevelEntity::Laser(Cardinal::S))),
+                'w' => entities.push((x, y, LevelEntity::Laser(Cardinal::W))),
+                '/' => entities.push((x, y, LevelEntity::Reflector(Cardinal::NE))),
+                '\\' => entities.push((x, y, LevelEntity::Reflector(Cardinal::NW))),
+                'x' => entities.push((x, y, LevelEntity::WeightPlate)),
+                'o' => entities.push((x, y, LevelEntity::LaserReceptor)),
+                'b' | '$' => entities.push((x, y, LevelEntity::Block)),
+                '@' => {
+                    if player.is_some() {
+                        return Err(LevelError::new(
+                            file,
+                            line_nb,
+                            column,
+                            LevelErrorKind::MultiplePlayers,
+                        ));
+                    }
+                    player = Some((x, y));
+                    entities.push((x, y, LevelEntity::Player));
                 }
-                'w' => {
-                    spawn_laser(gs, x, y, Cardinal::W);
+                c => {
+                    return Err(LevelError::new(
+                        file,
+                        line_nb,
+                        column,
+                        LevelErrorKind::UnknownGlyph(c),
+                    ))
                 }
-                '/' => {
-                    spawn_laser_reflector(gs, x, y, Cardinal::NE);
+            }
+        }
+    }
+    if player.is_none() {
+        return Err(LevelError::new(file, 0, 0, LevelErrorKind::MissingPlayer));
+    }
+    let (mut doors, behaviours) = parse_legend(
+        file,
+        &legend_lines,
+        width as i32,
+        height as i32,
+        &mut tiles,
+        &mut entities,
+    )?;
+    for (idx, error) in open_borders.into_iter() {
+        if tiles[idx] != TileType::Exit {
+            return Err(error);
+        }
+    }
+    let exit = (0..tiles.len())
+        .rev()
+        .find(|&idx| tiles[idx] == TileType::Exit)
+        .map(|idx| ((idx % width) as i32, (idx / width) as i32))
+        .ok_or_else(|| LevelError::new(file, 0, 0, LevelErrorKind::MissingExit))?;
+    if doors.is_empty() {
+        doors.push(DoorDescription {
+            name: String::from(EXIT_DOOR),
+            x: exit.0,
+            y: exit.1,
+            logic: exit_door_logic(&entities),
+        });
+    } else if let Some(idx) = (0..tiles.len()).find(|&idx| {
+        tiles[idx] == TileType::Exit
+            && !doors
+                .iter()
+                .any(|door| (door.y as usize) * width + door.x as usize == idx)
+    }) {
+        return Err(LevelError::new(
+            file,
+            0,
+            0,
+            LevelErrorKind::ExitWithoutDoor((idx % width) as i32, (idx / width) as i32),
+        ));
+    }
+    Ok(LevelDescription {
+        info,
+        width: width as i32,
+        height: height as i32,
+        tiles,
+        entities,
+        doors,
+        behaviours,
+        hash: content_hash(content),
+    })
+}
+
+/// FNV-1a hash of the level file content.
+pub fn content_hash(content: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in content.as_bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Logic of the door put on the exit when the legend has no door: every plate and receptor,
+/// in reading order.
+fn exit_door_logic(entities: &[(i32, i32, LevelEntity)]) -> Logic<Activator> {
+    let mut activators = entities
+        .iter()
+        .filter_map(|&(x, y, entity)| match entity {
+            LevelEntity::WeightPlate => Some(Activator::Plate(x, y)),
+            LevelEntity::LaserReceptor => Some(Activator::Receptor(x, y)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    activators.sort_by_key(|activator| {
+        let (x, y) = activator.position();
+        (y, x)
+    });
+    Logic::All(activators.into_iter().map(Logic::Is).collect())
+}
+
+/// Doors of a level without legend: a single door on the exit, wired by exit_door_logic.
+fn is_exit_door(
+    doors: &[DoorDescription],
+    exit: Option<(i32, i32)>,
+    entities: &[(i32, i32, LevelEntity)],
+) -> bool {
+    doors.len() == 1
+        && doors[0].name == EXIT_DOOR
+        && exit == Some((doors[0].x, doors[0].y))
+        && doors[0].logic == exit_door_logic(entities)
+}
+
+/// True when the level has no door declared in its legend, its exit door is wired
+/// to every plate and receptor.
+pub fn is_auto_wired(description: &LevelDescription) -> bool {
+    let width = description.width as usize;
+    let exit = (0..description.tiles.len())
+        .rev()
+        .find(|&idx| description.tiles[idx] == TileType::Exit)
+        .map(|idx| ((idx % width) as i32, (idx / width) as i32));
+    is_exit_door(&description.doors, exit, &description.entities)
+}
+
+/// Parse the legend lines, adding the plates and exits it declares to tiles and entities.
+fn parse_legend(
+    file: &str,
+    lines: &[(usize, &str)],
+    width: i32,
+    height: i32,
+    tiles: &mut Vec<TileType>,
+    entities: &mut Vec<(i32, i32, LevelEntity)>,
+) -> Result<(Vec<DoorDescription>, Vec<(i32, i32, ActivationBehaviour)>), LevelError> {
+    let mut items = vec![];
+    let mut labels = vec![];
+    for &(line_nb, line) in lines.iter() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (label, item) = parse_legend_line(line).map_err(|err| {
+            LevelError::new(
+                file,
+                line_nb,
+                err.column,
+                LevelErrorKind::InvalidLegend(err.message),
+            )
+        })?;
+        if labels.contains(&label) {
+            return Err(LevelError::new(
+                file,
+                line_nb,
+                1,
+                LevelErrorKind::InvalidLegend(format!("Label '{}' is already used", label)),
+            ));
+        }
+        labels.push(label.clone());
+        items.push((line_nb, label, item));
+    }
+    // Plates and exits change the grid content, they must be known before the items using them.
+    items.sort_by_key(|(_, _, item)| match item {
+        LegendItem::Plate { .. } | LegendItem::Exit { .. } => 0,
+        _ => 1,
+    });
+    let mut doors: Vec<DoorDescription> = vec![];
+    let mut behaviours: Vec<(i32, i32, ActivationBehaviour)> = vec![];
+    for (line_nb, label, item) in items.into_iter() {
+        let legend_error =
+            |message| LevelError::new(file, line_nb, 1, LevelErrorKind::InvalidLegend(message));
+        let (x, y) = item.position();
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return Err(legend_error(format!("({},{}) is outside the grid", x, y)));
+        }
+        let idx = (y * width + x) as usize;
+        if tiles[idx] == TileType::Wall {
+            return Err(legend_error(format!("({},{}) is a wall", x, y)));
+        }
+        let behaviour = match item {
+            LegendItem::Plate { .. } => {
+                if entities.contains(&(x, y, LevelEntity::WeightPlate))
+                    || entities.contains(&(x, y, LevelEntity::LaserReceptor))
+                {
+                    return Err(legend_error(format!("({},{}) cannot hold a plate", x, y)));
                 }
-                '\\' => {
-                    spawn_laser_reflector(gs, x, y, Cardinal::NW);
+                entities.push((x, y, LevelEntity::WeightPlate));
+                continue;
+            }
+            LegendItem::Exit { .. } => {
+                if tiles[idx] == TileType::Exit {
+                    return Err(legend_error(format!("({},{}) is already an exit", x, y)));
                 }
-                'x' => {
-                    activations.push(spawn_weight_plate(gs, x, y));
+                tiles[idx] = TileType::Exit;
+                continue;
+            }
+            LegendItem::Door { logic, .. } => {
+                if doors.iter().any(|door| door.x == x && door.y == y) {
+                    return Err(legend_error(format!("Several doors at ({},{})", x, y)));
                 }
-                'o' => {
-                    activations.push(spawn_laser_receptor(gs, x, y));
+                for activator in logic.leaves() {
+                    let (ax, ay) = activator.position();
+                    let (kind, name) = match activator {
+                        Activator::Plate(..) => (LevelEntity::WeightPlate, "plate"),
+                        Activator::Receptor(..) => (LevelEntity::LaserReceptor, "receptor"),
+                    };
+                    if !entities.contains(&(ax, ay, kind)) {
+                        return Err(legend_error(format!("No {} at ({},{})", name, ax, ay)));
+                    }
                 }
-                'b' | '$' => {
-                    spawn_block(gs, x, y);
+                doors.push(DoorDescription {
+                    name: label,
+                    x,
+                    y,
+                    logic,
+                });
+                continue;
+            }
+            LegendItem::Latch { .. } => ActivationBehaviour::Latching,
+            LegendItem::Timer { turns, .. } => {
+                if turns <= 0 {
+                    return Err(legend_error(String::from(
+                        "Timer needs a positive number of turns",
+                    )));
                 }
-                '@' => {
-                    spawn_player(gs, x, y);
+                ActivationBehaviour::Timed(turns)
+            }
+        };
+        if !entities.contains(&(x, y, LevelEntity::WeightPlate)) {
+            return Err(legend_error(format!("No plate at ({},{})", x, y)));
+        }
+        if behaviours.iter().any(|&(bx, by, _)| bx == x && by == y) {
+            return Err(legend_error(format!(
+                "Plate ({},{}) already has a behaviour",
+                x, y
+            )));
+        }
+        behaviours.push((x, y, behaviour));
+    }
+    Ok((doors, behaviours))
+}
+
+fn parse_header(
+    file: &str,
+    line_nb: usize,
+    key: &str,
+    value: &str,
+    info: &mut LevelInfo,
+) -> Result<(), LevelError> {
+    let key = key.trim();
+    let value = value.trim();
+    let parse_number = || {
+        value
+            .parse::<i32>()
+            .ok()
+            .filter(|&x| x >= 0)
+            .ok_or_else(|| {
+                LevelError::new(
+                    file,
+                    line_nb,
+                    1,
+                    LevelErrorKind::InvalidHeaderValue(String::from(key)),
+                )
+            })
+    };
+    match key {
+        "title" => info.title = Some(String::from(value)),
+        "author" => info.author = Some(String::from(value)),
+        "par_steps" => info.par_steps = Some(parse_number()?),
+        "par_energy" => info.par_energy = Some(parse_number()?),
+        "hint" => info.hint = Some(String::from(value)),
+        _ => {
+            return Err(LevelError::new(
+                file,
+                line_nb,
+                1,
+                LevelErrorKind::UnknownHeaderKey(String::from(key)),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Replace the current World content with the given level.
+pub fn build_level(
+    ecs: &mut World,
+    rsrc: &mut Resources,
+    level: Option<LevelRef>,
+    description: &LevelDescription,
+) {
+    ecs.delete_all();
+    let mut map = map::Map::new(level, description.width, description.height);
+    for (idx, &tiletype) in description.tiles.iter().enumerate() {
+        let x = idx as i32 % description.width;
+        let y = idx as i32 / description.width;
+        map.set_tiletype(x, y, tiletype);
+    }
+    let mut activators = vec![];
+    for &(x, y, entity) in description.entities.iter() {
+        match entity {
+            LevelEntity::Player => {
+                spawn_player(ecs, x, y);
+            }
+            LevelEntity::Block => {
+                spawn_block(ecs, x, y);
+            }
+            LevelEntity::WeightPlate => {
+                let behaviour = description
+                    .behaviours
+                    .iter()
+                    .find(|&&(bx, by, _)| bx == x && by == y)
+                    .map_or(ActivationBehaviour::Momentary, |&(_, _, behaviour)| {
+                        behaviour
+                    });
+                let entity = spawn_weight_plate(ecs, x, y, behaviour);
+                activators.push((Activator::Plate(x, y), entity))
+            }
+            LevelEntity::LaserReceptor => {
+                activators.push((Activator::Receptor(x, y), spawn_laser_receptor(ecs, x, y)))
+            }
+            LevelEntity::Laser(direction) => {
+                spawn_laser(ecs, x, y, direction);
+            }
+            LevelEntity::Reflector(orientation) => {
+                spawn_laser_reflector(ecs, x, y, orientation);
+            }
+        }
+    }
+    for door in description.doors.iter() {
+        // Activators have been checked to exist while parsing the legend.
+        let logic = door.logic.map(&|activator: &Activator| {
+            activators
+                .iter()
+                .find(|(a, _)| a == activator)
+                .map(|&(_, entity)| entity)
+                .unwrap()
+        });
+        spawn_door(ecs, &door.name, door.x, door.y, logic);
+    }
+    rsrc.insert(map);
+    rsrc.insert(description.info.clone());
+    rsrc.insert(LevelHash(description.hash));
+    rsrc.insert(TurnsHistory::new());
+}
+
+/// Write the World in the text format read by parse_level, parsing it back rebuilds the same level.
+/// Latch and timer memory is not saved, plates are released when the level is loaded again.
+pub fn save_level(ecs: &World, map: &map::Map) -> String {
+    let entities = describe_entities(ecs);
+    let mut grid = vec![vec!['.'; map.width as usize]; map.height as usize];
+    for y in 0..map.height {
+        for x in 0..map.width {
+            grid[y as usize][x as usize] = match map.tiletype(x, y) {
+                TileType::Wall => '#',
+                TileType::Exit => 'E',
+                TileType::Floor => '.',
+            };
+        }
+    }
+    for &(x, y, entity) in entities.iter() {
+        grid[y as usize][x as usize] = match entity {
+            LevelEntity::Player => '@',
+            LevelEntity::Block => 'b',
+            LevelEntity::LaserReceptor => 'o',
+            LevelEntity::Laser(Cardinal::N) => 'n',
+            LevelEntity::Laser(Cardinal::S) => 's',
+            LevelEntity::Laser(Cardinal::E) => 'e',
+            LevelEntity::Laser(_) => 'w',
+            LevelEntity::Reflector(Cardinal::NE) => '/',
+            LevelEntity::Reflector(_) => '\\',
+            LevelEntity::WeightPlate => continue,
+        };
+    }
+    let mut legend = vec![];
+    for &(x, y, entity) in entities.iter() {
+        if entity == LevelEntity::WeightPlate {
+            let cell = &mut grid[y as usize][x as usize];
+            match *cell {
+                '.' => *cell = 'x',
+                'b' => *cell = '*',
+                _ => legend.push(LegendItem::Plate { x, y }),
+            }
+        }
+    }
+    let mut exit = None;
+    for y in 0..map.height {
+        for x in 0..map.width {
+            if map.tiletype(x, y) == TileType::Exit {
+                exit = Some((x, y));
+                if grid[y as usize][x as usize] != 'E' {
+                    legend.push(LegendItem::Exit { x, y });
                 }
-                x => println!("Unused {}", x),
             }
         }
     }
-    spawn_door(gs, exit.0, exit.1, activations);
-    gs.rsrc.insert(map);
-    gs.rsrc.insert(TurnsHistory::new());
+    let mut behaviours = <(Read<Activable>, Read<Position>)>::query()
+        .iter(ecs)
+        .filter_map(|(activable, pos)| match activable.behaviour {
+            ActivationBehaviour::Momentary => None,
+            ActivationBehaviour::Latching => Some(LegendItem::Latch { x: pos.x, y: pos.y }),
+            ActivationBehaviour::Timed(turns) => Some(LegendItem::Timer {
+                x: pos.x,
+                y: pos.y,
+                turns,
+            }),
+        })
+        .collect::<Vec<_>>();
+    behaviours.sort_by_key(|item| {
+        let (x, y) = item.position();
+        (y, x)
+    });
+    legend.extend(behaviours);
+    let to_activator = |entity: &Entity| {
+        let pos = ecs.get_component::<Position>(*entity).unwrap();
+        match ecs.get_component::<Activable>(*entity).unwrap().kind {
+            ActivationKind::Weight => Activator::Plate(pos.x, pos.y),
+            ActivationKind::Laser => Activator::Receptor(pos.x, pos.y),
+        }
+    };
+    let mut doors = <(Read<Door>, Read<Position>)>::query()
+        .iter(ecs)
+        .map(|(door, pos)| DoorDescription {
+            name: door.name.clone(),
+            x: pos.x,
+            y: pos.y,
+            logic: door.logic.map(&to_activator),
+        })
+        .collect::<Vec<_>>();
+    doors.sort_by_key(|door| (door.y, door.x));
+    let auto_wired = is_exit_door(&doors, exit, &entities);
+    let mut used_labels = doors
+        .iter()
+        .map(|door| door.name.clone())
+        .collect::<Vec<_>>();
+    let mut lines = vec![];
+    for item in legend.into_iter() {
+        let prefix = match item {
+            LegendItem::Plate { .. } => "P",
+            LegendItem::Exit { .. } => "X",
+            LegendItem::Latch { .. } => "L",
+            LegendItem::Timer { .. } => "T",
+            LegendItem::Door { .. } => "D",
+        };
+        let label = (1..)
+            .map(|i| format!("{}{}", prefix, i))
+            .find(|label| !used_labels.contains(label))
+            .unwrap();
+        lines.push(format!("{}: {}", label, item));
+        used_labels.push(label);
+    }
+    if !auto_wired {
+        for door in doors.into_iter() {
+            let item = LegendItem::Door {
+                x: door.x,
+                y: door.y,
+                logic: door.logic,
+            };
+            lines.push(format!("{}: {}", door.name, item));
+        }
+    }
+    let mut content = String::new();
+    for row in grid.iter() {
+        content.extend(row.iter());
+        content.push('\n');
+    }
+    if !lines.is_empty() {
+        content.push('\n');
+        for line in lines.iter() {
+            content.push_str(line);
+            content.push('\n');
+        }
+    }
+    content
+}
+
+/// Same as save_level, preceded by the header holding the level metadata.
+pub fn save_level_with_info(ecs: &World, map: &map::Map, info: &LevelInfo) -> String {
+    let mut content = String::new();
+    let texts = [
+        ("title", info.title.clone()),
+        ("author", info.author.clone()),
+        ("par_steps", info.par_steps.map(|x| x.to_string())),
+        ("par_energy", info.par_energy.map(|x| x.to_string())),
+        ("hint", info.hint.clone()),
+    ];
+    for (key, value) in texts.iter() {
+        if let Some(value) = value {
+            content.push_str(&format!("{}: {}\n", key, value));
+        }
+    }
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    content.push_str(&save_level(ecs, map));
+    content
+}
+
+/// Replace the doors of the World by a single door on the exit, wired to every plate and receptor
+/// in reading order, as for a level without legend. The exit is the last one in reading order.
+pub fn wire_exit_door(ecs: &mut World, map: &map::Map) {
+    let doors = <(Read<Door>,)>::query()
+        .iter_entities(ecs)
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    for door in doors {
+        ecs.delete(door);
+    }
+    let exit = map.get_exits().into_iter().max_by_key(|&(x, y)| (y, x));
+    if let Some((x, y)) = exit {
+        let mut activables = <(Read<Activable>, Read<Position>)>::query()
+            .iter_entities(ecs)
+            .map(|(entity, (_, pos))| (pos.y, pos.x, entity))
+            .collect::<Vec<_>>();
+        activables.sort_by_key(|&(y, x, _)| (y, x));
+        let logic = Logic::All(
+            activables
+                .into_iter()
+                .map(|(_, _, entity)| Logic::Is(entity))
+                .collect(),
+        );
+        spawn_door(ecs, EXIT_DOOR, x, y, logic);
+    }
 }
 
-fn spawn_player(gs: &mut State, x: i32, y: i32) -> Entity {
-    gs.ecs.insert(
+/// Entities of the World as found in a level file, in reading order.
+fn describe_entities(ecs: &World) -> Vec<(i32, i32, LevelEntity)> {
+    let mut entities = vec![];
+    for (pos,) in <(Read<Position>,)>::query()
+        .filter(tag::<Player>())
+        .iter(ecs)
+    {
+        entities.push((pos.x, pos.y, LevelEntity::Player));
+    }
+    for (pos,) in <(Read<Position>,)>::query()
+        .filter(tag::<Block>())
+        .iter(ecs)
+    {
+        entities.push((pos.x, pos.y, LevelEntity::Block));
+    }
+    for (laser, pos) in <(Tagged<Laser>, Read<Position>)>::query().iter(ecs) {
+        entities.push((pos.x, pos.y, LevelEntity::Laser(laser.direction)));
+    }
+    for (reflector, pos) in <(Read<ReflectsLaser>, Read<Position>)>::query().iter(ecs) {
+        entities.push((pos.x, pos.y, LevelEntity::Reflector(reflector.orientation)));
+    }
+    for (activable, pos) in <(Read<Activable>, Read<Position>)>::query().iter(ecs) {
+        let entity = match activable.kind {
+            ActivationKind::Weight => LevelEntity::WeightPlate,
+            ActivationKind::Laser => LevelEntity::LaserReceptor,
+        };
+        entities.push((pos.x, pos.y, entity));
+    }
+    entities.sort_by_key(|&(x, y, _)| (y, x));
+    entities
+}
+
+pub fn spawn_player(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.insert(
         (Player {},), // BlocksTile {}),
-        vec![(
-            Position { x, y },
-            Renderable {
-                glyph: PLAYER,
-                fg: RGB::named(YELLOW),
-                bg: RGB::named(BLACK),
-                render_order: 0,
-            },
-        )],
+        vec![(Position { x, y },)],
     )[0]
 }
 
-fn spawn_laser(gs: &mut State, x: i32, y: i32, direction: Cardinal) -> Entity {
-    gs.ecs.insert(
+pub fn spawn_laser(ecs: &mut World, x: i32, y: i32, direction: Cardinal) -> Entity {
+    ecs.insert(
         (
             Laser { direction },
             BlocksTile {},
             BlocksLaser {},
             Movable {},
         ),
-        vec![(
-            Position { x, y },
-            Renderable {
-                glyph: match direction {
-                    Cardinal::N => LASER_N,
-                    Cardinal::E => LASER_E,
-                    Cardinal::W => LASER_W,
-                    _ => LASER_S,
-                },
-                fg: RGB::named(LIGHT_BLUE),
-                bg: RGB::named(BLACK),
-                render_order: 1,
-            },
-        )],
+        vec![(Position { x, y },)],
     )[0]
 }
 
-fn spawn_block(gs: &mut State, x: i32, y: i32) -> Entity {
-    gs.ecs.insert(
+pub fn spawn_block(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.insert(
         (Block {}, BlocksTile {}, Movable {}, BlocksLaser {}),
-        vec![(
-            Position { x, y },
-            Renderable {
-                glyph: MOVABLE_BLOCK,
-                fg: RGB::named(ORANGE),
-                bg: RGB::named(BLACK),
-                render_order: 1,
-            },
-        )],
+        vec![(Position { x, y },)],
     )[0]
 }
 
-fn spawn_weight_plate(gs: &mut State, x: i32, y: i32) -> Entity {
-    gs.ecs.insert(
+pub fn spawn_weight_plate(
+    ecs: &mut World,
+    x: i32,
+    y: i32,
+    behaviour: ActivationBehaviour,
+) -> Entity {
+    ecs.insert(
         (),
         vec![(
             Position { x, y },
             Activable {
                 active: false,
                 kind: ActivationKind::Weight,
-            },
-            Renderable {
-                glyph: WEIGHT_PLATE,
-                fg: RGB::named(RED),
-                bg: RGB::named(BLACK),
-                render_order: 2,
+                behaviour,
+                latched: false,
+                last_triggered: None,
             },
         )],
     )[0]
 }
-fn spawn_laser_receptor(gs: &mut State, x: i32, y: i32) -> Entity {
-    gs.ecs.insert(
+pub fn spawn_laser_receptor(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.insert(
         (BlocksTile {}, BlocksLaser {}),
         vec![(
             Position { x, y },
             Activable {
                 active: false,
                 kind: ActivationKind::Laser,
-            },
-            Renderable {
-                glyph: LASER_RECEPTOR,
-                fg: RGB::named(ORANGE),
-                bg: RGB::named(BLACK),
-                render_order: 2,
+                behaviour: ActivationBehaviour::Momentary,
+                latched: false,
+                last_triggered: None,
             },
         )],
     )[0]
 }
 
-fn spawn_door(gs: &mut State, x: i32, y: i32, activations: Vec<Entity>) -> Entity {
-    gs.ecs.insert(
+pub fn spawn_door(ecs: &mut World, name: &str, x: i32, y: i32, logic: Logic<Entity>) -> Entity {
+    ecs.insert(
         (BlocksLaser {},),
         vec![(
             Position { x, y },
             Door {
+                name: String::from(name),
                 opened: false,
-                activations: activations,
-            },
-            Renderable {
-                glyph: DOOR_H_CLOSED,
-                fg: RGB::named(RED),
-                bg: RGB::named(BLACK),
-                render_order: 1,
+                logic,
             },
         )],
     )[0]
 }
 
-fn spawn_laser_reflector(gs: &mut State, x: i32, y: i32, orientation: Cardinal) -> Entity {
-    gs.ecs.insert(
+pub fn spawn_laser_reflector(ecs: &mut World, x: i32, y: i32, orientation: Cardinal) -> Entity {
+    ecs.insert(
         (BlocksTile {}, Movable {}),
         vec![(
             Position { x, y },
-            Renderable {
-                glyph: if orientation == Cardinal::NE {
-                    REFLECTOR_NE
-                } else {
-                    REFLECTOR_NW
-                },
-                fg: RGB::named(WHITE),
-                bg: RGB::named(BLACK),
-                render_order: 1,
-            },
             ReflectsLaser {
                 orientation: orientation,
             },
@@ -225,3 +902,160 @@ fn spawn_laser_reflector(gs: &mut State, x: i32, y: i32, orientation: Cardinal)
         )],
     )[0]
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Every level of the built-in packs, with its file.
+    pub fn shipped_levels() -> Vec<(String, LevelDescription)> {
+        let packs = LevelPacks::load().unwrap();
+        let mut levels = vec![];
+        for pack in packs.packs.iter() {
+            for index in 0..pack.levels.len() {
+                let file = pack.level_path(index).unwrap();
+                let description = read_level(&file).unwrap_or_else(|err| panic!("{}", err));
+                levels.push((file, description));
+            }
+        }
+        levels
+    }
+
+    /// Build the level, save it and parse it back, it must describe the same level.
+    fn assert_round_trip(file: &str, description: &LevelDescription) {
+        let mut ecs = Universe::new().create_world();
+        let mut rsrc = Resources::default();
+        build_level(&mut ecs, &mut rsrc, None, description);
+        let content =
+            save_level_with_info(&ecs, &rsrc.get::<map::Map>().unwrap(), &description.info);
+        let saved = parse_level(file, &content)
+            .unwrap_or_else(|err| panic!("{}, saved as:\n{}", err, content));
+        assert_eq!(saved.info, description.info, "{}", file);
+        assert_eq!(saved.width, description.width, "{}", file);
+        assert_eq!(saved.height, description.height, "{}", file);
+        assert_eq!(saved.tiles, description.tiles, "{}", file);
+        assert_eq!(saved.entities, description.entities, "{}", file);
+        assert_eq!(saved.doors, description.doors, "{}", file);
+        assert_eq!(saved.behaviours, description.behaviours, "{}", file);
+    }
+
+    #[test]
+    fn shipped_levels_round_trip() {
+        for (file, description) in shipped_levels().iter() {
+            assert_round_trip(file, description);
+        }
+    }
+
+    #[test]
+    fn glyphs_and_legend_round_trip() {
+        let content = "\
+title: Round trip
+par_steps: 12
+
+#####E##
+#@..x\\.#
+#.*.../#
+#..o.e.#
+#.x....#
+########
+
+A: door(5,0) <- plate(4,1), any(receptor(3,3), not(plate(2,2)))
+P: plate(5,3)
+T: timer(4,1,3)
+L: latch(2,4)
+";
+        let description = parse_level("round_trip.txt", content).unwrap();
+        assert_eq!(description.doors.len(), 1);
+        assert_eq!(description.behaviours.len(), 2);
+        assert_round_trip("round_trip.txt", &description);
+    }
+
+    /// Parse the level, which must fail, and return the kind and position of the error.
+    fn parse_error(content: &str) -> (LevelErrorKind, usize, usize) {
+        let err = parse_level("error.txt", content).unwrap_err();
+        assert_eq!(err.file, "error.txt");
+        (err.kind, err.line, err.column)
+    }
+
+    #[test]
+    fn unknown_glyph_is_reported_at_its_position() {
+        assert_eq!(
+            parse_error("##E##\n#@.?#\n#####\n"),
+            (LevelErrorKind::UnknownGlyph('?'), 2, 4)
+        );
+    }
+
+    #[test]
+    fn ragged_row_is_reported_at_its_first_missing_or_extra_column() {
+        assert_eq!(
+            parse_error("##E##\n#@.#\n#####\n"),
+            (
+                LevelErrorKind::RaggedRow {
+                    expected: 5,
+                    found: 4
+                },
+                2,
+                5
+            )
+        );
+        assert_eq!(
+            parse_error("##E##\n#@..#\n######\n"),
+            (
+                LevelErrorKind::RaggedRow {
+                    expected: 5,
+                    found: 6
+                },
+                3,
+                6
+            )
+        );
+    }
+
+    #[test]
+    fn missing_player_has_no_position() {
+        assert_eq!(
+            parse_error("##E##\n#...#\n#####\n"),
+            (LevelErrorKind::MissingPlayer, 0, 0)
+        );
+    }
+
+    #[test]
+    fn second_player_is_reported_at_its_position() {
+        assert_eq!(
+            parse_error("##E##\n#@..#\n#.@.#\n#####\n"),
+            (LevelErrorKind::MultiplePlayers, 3, 3)
+        );
+    }
+
+    #[test]
+    fn missing_exit_has_no_position() {
+        assert_eq!(
+            parse_error("#####\n#@..#\n#####\n"),
+            (LevelErrorKind::MissingExit, 0, 0)
+        );
+    }
+
+    #[test]
+    fn open_border_is_reported_at_its_position() {
+        assert_eq!(
+            parse_error("##E##\n.@..#\n#####\n"),
+            (LevelErrorKind::UnclosedBorder('.'), 2, 1)
+        );
+    }
+
+    #[test]
+    fn lines_are_counted_from_the_header() {
+        assert_eq!(
+            parse_error("title: Errors\n\n##E##\n#@.?#\n#####\n"),
+            (LevelErrorKind::UnknownGlyph('?'), 4, 4)
+        );
+        assert_eq!(
+            parse_error("title: Errors\nsize: 3\n##E##\n#@..#\n#####\n"),
+            (LevelErrorKind::UnknownHeaderKey(String::from("size")), 2, 1)
+        );
+        assert_eq!(
+            parse_error("title: Errors\n\n"),
+            (LevelErrorKind::Empty, 0, 0)
+        );
+    }
+}
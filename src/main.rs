@@ -1,16 +1,29 @@
 use bracket_lib::prelude::*;
 use legion::prelude::*;
 mod components;
-use components::{Position, Renderable};
 use gui::{draw_ui, MainMenuSelection};
+use pack::{LevelPacks, LevelRef};
 use turn_history::{TurnState, TurnsHistory};
 mod ai;
 mod ai_cache;
+mod assets;
+mod check;
+mod cli;
+mod editor;
+mod game_core;
+mod game_state;
 mod glyphs;
 mod gui;
+mod legend;
 mod level;
 mod map;
+mod pack;
 mod player;
+mod progress;
+mod render;
+mod replay;
+mod sokoban;
+mod solver;
 mod systems;
 mod turn_history;
 
@@ -20,7 +33,10 @@ pub const TERM_HEIGHT: i32 = 30;
 #[derive(Clone, Copy, PartialEq)]
 pub enum RunState {
     MainMenu { menu_selection: MainMenuSelection },
-    LoadLevel(u64),
+    LoadLevel(LevelRef),
+    LevelError,
+    LevelSelect(LevelRef),
+    Editor,
     GameAwaitingInput,
     GameTurn,
     GameDraw,
@@ -30,19 +46,36 @@ pub struct State {
     pub rsrc: Resources,
     schedule: Schedule,
     ai: Option<ai::AI>,
+    replay: Option<replay::ReplayPlayer>,
+    /// Level shown in the level select screen
+    preview: Option<(LevelRef, Result<game_core::Game, String>)>,
+    editor: Option<editor::Editor>,
 }
 impl GameState for State {
     fn tick(&mut self, ctx: &mut BTerm) {
         let runstate = *(self.rsrc.get::<RunState>().unwrap());
+        // F5 ends the play-test only while the level is played, not in the menus it can be left to.
+        let playing = match runstate {
+            RunState::GameAwaitingInput | RunState::GameDraw => true,
+            _ => false,
+        };
+        if let Some(editor) = self.editor.as_mut().filter(|_| playing) {
+            if editor.is_playtesting() && ctx.key == Some(VirtualKeyCode::F5) {
+                editor.stop_playtest(&mut self.ecs, &mut self.rsrc);
+                self.rsrc.insert(RunState::Editor);
+                return;
+            }
+        }
         let newrunstate;
         match runstate {
             RunState::MainMenu {
                 menu_selection: selection,
             } => {
-                let map = self.rsrc.get::<map::Map>().unwrap();
-                let can_continue = map.level != 0;
+                let continue_level = self.continue_level();
+                let can_continue = continue_level.is_some();
                 ctx.cls();
                 let result = gui::main_menu(ctx, selection, can_continue);
+                gui::draw_message(&self.rsrc, ctx);
                 match result {
                     gui::MainMenuResult::NoSelection { selected } => {
                         newrunstate = RunState::MainMenu {
@@ -52,37 +85,135 @@ impl GameState for State {
                     gui::MainMenuResult::Selected { selected } => match selected {
                         gui::MainMenuSelection::NewPlayerGame => {
                             self.ai = None;
-                            newrunstate = RunState::LoadLevel(1)
+                            self.replay = None;
+                            self.editor = None;
+                            newrunstate = self.start_pack(pack::MAIN_PACK);
                         }
                         gui::MainMenuSelection::NewAiGame => {
-                            self.ai = Some(ai::AI::new());
-                            newrunstate = RunState::LoadLevel(10001)
+                            self.ai = Some(ai::AI::new(ai::AiMode::Explore, ai::DEFAULT_SEED));
+                            self.replay = None;
+                            self.editor = None;
+                            newrunstate = self.start_pack(pack::AI_TESTS_PACK);
+                        }
+                        gui::MainMenuSelection::LevelSelect => {
+                            newrunstate = match self.level_select_start() {
+                                Some(level) => RunState::LevelSelect(level),
+                                None => RunState::MainMenu {
+                                    menu_selection: selection,
+                                },
+                            };
+                        }
+                        gui::MainMenuSelection::Editor => {
+                            self.ai = None;
+                            self.replay = None;
+                            newrunstate = self.start_editor();
+                        }
+                        gui::MainMenuSelection::Continue => {
+                            let playing = self.rsrc.get::<map::Map>().unwrap().level.is_some();
+                            newrunstate = match continue_level {
+                                Some(_) if playing => RunState::GameDraw,
+                                Some(level) => RunState::LoadLevel(level),
+                                None => RunState::MainMenu {
+                                    menu_selection: selection,
+                                },
+                            };
                         }
-                        gui::MainMenuSelection::Continue => newrunstate = RunState::GameDraw,
                         gui::MainMenuSelection::Quit => {
                             ::std::process::exit(0);
                         }
                     },
                 }
             }
-            RunState::LoadLevel(level) => {
-                level::load_level(self, level);
-                self.run_game_systems();
-                ctx.cls();
-                if self.ai.as_ref().map_or(true, |x| x.show) {
-                    self.draw_game(ctx);
+            RunState::LoadLevel(level) => match level::load_level(self, level) {
+                Ok(()) => {
+                    self.rsrc.insert(gui::UiMessage::default());
+                    if self.ai.is_none() {
+                        self.save_progress(|progress, packs| progress.set_current(packs, level));
+                    }
+                    // Doors opened at the start only update the map on the second run.
+                    self.run_game_systems();
+                    self.run_game_systems();
+                    ctx.cls();
+                    if self.ai.as_ref().map_or(true, |x| x.show) {
+                        self.draw_game(ctx);
+                    }
+                    newrunstate = RunState::GameAwaitingInput;
                 }
-                newrunstate = RunState::GameAwaitingInput;
+                Err(error) => {
+                    self.rsrc.insert(error);
+                    newrunstate = RunState::LevelError;
+                }
+            },
+            RunState::LevelSelect(level) => {
+                ctx.cls();
+                let result = gui::level_select(&self.rsrc, ctx, level);
+                self.draw_preview(level, ctx);
+                newrunstate = match result {
+                    gui::LevelSelectResult::NoSelection { selected } => {
+                        RunState::LevelSelect(selected)
+                    }
+                    gui::LevelSelectResult::Selected { selected } => {
+                        self.ai = None;
+                        self.replay = None;
+                        self.editor = None;
+                        self.preview = None;
+                        RunState::LoadLevel(selected)
+                    }
+                    gui::LevelSelectResult::Cancel => {
+                        self.preview = None;
+                        RunState::MainMenu {
+                            menu_selection: MainMenuSelection::LevelSelect,
+                        }
+                    }
+                };
+            }
+            RunState::Editor => {
+                let editor = self.editor.as_mut().unwrap();
+                newrunstate = editor.input(&mut self.ecs, &mut self.rsrc, ctx);
+                editor.run_systems(&mut self.ecs, &mut self.rsrc);
+                ctx.cls();
+                render::draw_level(&self.ecs, &self.rsrc, ctx, 11);
+                editor.draw(&self.rsrc.get::<map::Map>().unwrap(), ctx, 11);
+            }
+            RunState::LevelError => {
+                ctx.cls();
+                gui::draw_level_error(&self.rsrc, ctx);
+                newrunstate = gui::level_error_input(ctx);
             }
             RunState::GameAwaitingInput => {
                 if let Some(ai) = self.ai.as_mut() {
                     newrunstate = ai.play_next_turn(&mut self.ecs, &mut self.rsrc, ctx);
+                } else if let Some(replay) = self.replay.as_mut() {
+                    newrunstate = replay.play_next_turn(&mut self.ecs, &mut self.rsrc, ctx);
+                    if replay.finished {
+                        self.replay = None;
+                    }
                 } else {
-                    newrunstate = player::game_turn_input(self, ctx);
+                    newrunstate = gui::game_turn_input(self, ctx);
                 }
             }
             RunState::GameTurn => {
                 self.run_game_systems();
+                let solved =
+                    self.rsrc.get::<TurnsHistory>().unwrap().state == TurnState::PlayerAtExit;
+                if solved && self.replay.is_some() {
+                    // The replay is over, the level solved screen is the player's again.
+                    self.replay = None;
+                } else if solved && self.ai.is_none() {
+                    if let Some(message) = self.save_replay() {
+                        self.rsrc.insert(message);
+                    }
+                    let level = self.rsrc.get::<map::Map>().unwrap().level;
+                    let (steps, energy) = {
+                        let history = self.rsrc.get::<TurnsHistory>().unwrap();
+                        (history.steps, history.energy_used)
+                    };
+                    if let Some(level) = level {
+                        self.save_progress(|progress, packs| {
+                            progress.solved(packs, level, steps, energy)
+                        });
+                    }
+                }
                 newrunstate = RunState::GameDraw;
             }
             RunState::GameDraw => {
@@ -102,6 +233,9 @@ impl GameState for State {
                         TurnState::Running => {}
                     };
                 }
+                if let Some(replay) = self.replay.as_ref() {
+                    replay.draw_state(ctx);
+                }
                 if self.ai.is_none() {
                     newrunstate = match curstate {
                         TurnState::PlayerDead => {
@@ -133,37 +267,171 @@ impl State {
             rsrc: resources,
             schedule: systems::build_systems(),
             ai: None,
+            replay: None,
+            preview: None,
+            editor: None,
+        }
+    }
+    /// RunState loading the first level of the named pack.
+    fn start_pack(&mut self, name: &str) -> RunState {
+        let first_level = self.rsrc.get::<LevelPacks>().unwrap().first_level(name);
+        match first_level {
+            Ok(level) => RunState::LoadLevel(level),
+            Err(error) => {
+                self.rsrc.insert(error);
+                RunState::LevelError
+            }
+        }
+    }
+    /// Level resumed by Continue: the one being played, or the last one played in a previous run.
+    fn continue_level(&self) -> Option<LevelRef> {
+        let map = self.rsrc.get::<map::Map>().unwrap();
+        let packs = self.rsrc.get::<LevelPacks>().unwrap();
+        let progress = self.rsrc.get::<progress::Progress>().unwrap();
+        map.level.or_else(|| progress.current_level(&packs))
+    }
+    /// Level first selected in the level select screen: the one resumed by Continue,
+    /// or the first level of the main pack. None when there is no level at all.
+    fn level_select_start(&self) -> Option<LevelRef> {
+        let packs = self.rsrc.get::<LevelPacks>().unwrap();
+        self.continue_level()
+            .or_else(|| packs.first_level(pack::MAIN_PACK).ok())
+            .or_else(|| {
+                (0..packs.packs.len())
+                    .find(|&pack| !packs.packs[pack].levels.is_empty())
+                    .map(|pack| LevelRef { pack, index: 0 })
+            })
+    }
+    /// RunState of the editor, resuming the current edit if there is one,
+    /// or else editing the level being played, or a blank level.
+    fn start_editor(&mut self) -> RunState {
+        if let Some(editor) = self.editor.as_mut() {
+            editor.stop_playtest(&mut self.ecs, &mut self.rsrc);
+            return RunState::Editor;
+        }
+        let level = self.rsrc.get::<map::Map>().unwrap().level;
+        let file = level.and_then(|level| self.rsrc.get::<LevelPacks>().unwrap().level_path(level));
+        let description = match file.map(|file| level::read_level(&file)) {
+            Some(Ok(description)) => Some(description),
+            Some(Err(error)) => {
+                self.rsrc.insert(error);
+                return RunState::LevelError;
+            }
+            None => None,
+        };
+        self.editor = Some(editor::Editor::new(
+            &mut self.ecs,
+            &mut self.rsrc,
+            description,
+        ));
+        RunState::Editor
+    }
+    /// Update the progress and write it to the save file, telling the player when it fails.
+    fn save_progress<F: FnOnce(&mut progress::Progress, &LevelPacks)>(&mut self, update: F) {
+        let result = {
+            let packs = self.rsrc.get::<LevelPacks>().unwrap();
+            let mut progress = self.rsrc.get_mut::<progress::Progress>().unwrap();
+            update(&mut progress, &packs);
+            progress.save()
+        };
+        if let Err(err) = result {
+            self.rsrc
+                .insert(gui::UiMessage(format!("Cannot save progress: {}", err)));
+        }
+    }
+    /// Write the replay of the level just solved, returning the message telling where.
+    fn save_replay(&self) -> Option<gui::UiMessage> {
+        let map = self.rsrc.get::<map::Map>().unwrap();
+        let packs = self.rsrc.get::<LevelPacks>().unwrap();
+        let level = map.level?;
+        let hash = self.rsrc.get::<level::LevelHash>().unwrap().0;
+        let history = self.rsrc.get::<TurnsHistory>().unwrap();
+        let replay = replay::Replay::record(
+            &packs.packs[level.pack].name,
+            &packs.get(level).unwrap().id,
+            hash,
+            &history,
+        );
+        replay.map(|replay| {
+            gui::UiMessage(match replay.save() {
+                Ok(path) => format!("Replay saved to {}", path.display()),
+                Err(err) => format!("Cannot save replay: {}", err),
+            })
+        })
+    }
+    /// RunState restarting the current level to watch its replay.
+    fn watch_replay(&mut self) -> RunState {
+        let level = match self.rsrc.get::<map::Map>().unwrap().level {
+            Some(level) => level,
+            None => return RunState::GameDraw,
+        };
+        let hash = self.rsrc.get::<level::LevelHash>().unwrap().0;
+        let replay = {
+            let packs = self.rsrc.get::<LevelPacks>().unwrap();
+            replay::Replay::load(&packs.packs[level.pack].name, &packs.get(level).unwrap().id)
+        };
+        let player = replay.and_then(|replay| {
+            if replay.matches(hash) {
+                replay::ReplayPlayer::new(&replay)
+            } else {
+                Err(String::from(
+                    "Replay was recorded on another version of the level",
+                ))
+            }
+        });
+        match player {
+            Ok(player) => {
+                self.replay = Some(player);
+                RunState::LoadLevel(level)
+            }
+            Err(err) => {
+                self.rsrc
+                    .insert(gui::UiMessage(format!("Cannot watch replay: {}", err)));
+                RunState::GameDraw
+            }
         }
     }
     fn run_game_systems(&mut self) {
         self.schedule.execute(&mut self.ecs, &mut self.rsrc);
     }
     fn draw_game(&self, ctx: &mut BTerm) {
-        let map = self.rsrc.get::<map::Map>().unwrap();
-        let start_x = (TERM_WIDTH - map.width) / 2;
-        let start_y = 11;
-        map.draw(ctx, start_x, start_y);
-        let mut data = <(Read<Position>, Read<Renderable>)>::query()
-            .iter(&self.ecs)
-            .collect::<Vec<_>>();
-        data.sort_by(|d1, d2| d2.1.render_order.cmp(&d1.1.render_order));
-        for (pos, render) in data.iter() {
-            ctx.set(
-                start_x + pos.x,
-                start_y + pos.y,
-                render.fg,
-                render.bg,
-                render.glyph,
-            );
-        }
+        render::draw_level(&self.ecs, &self.rsrc, ctx, 11);
         draw_ui(&self.rsrc, ctx);
     }
+    /// Draw the preview of the level selected in the level select screen,
+    /// loading it first when the selection changed.
+    fn draw_preview(&mut self, level: LevelRef, ctx: &mut BTerm) {
+        if self
+            .preview
+            .as_ref()
+            .map_or(true, |(previewed, _)| *previewed != level)
+        {
+            let file = self.rsrc.get::<LevelPacks>().unwrap().level_path(level);
+            let preview = file
+                .ok_or_else(|| String::from("Level not found in its pack"))
+                .and_then(|file| level::read_level(&file).map_err(|err| err.message()))
+                .map(|description| {
+                    game_core::Game::new(&description, systems::build_laser_systems())
+                });
+            self.preview = Some((level, preview));
+        }
+        match &self.preview.as_ref().unwrap().1 {
+            Ok(preview) => render::draw_level(&preview.ecs, &preview.rsrc, ctx, 12),
+            Err(err) => {
+                ctx.print_color_centered(12, RGB::named(RED), RGB::named(BLACK), err);
+            }
+        }
+    }
 }
 
 pub const TERM_UI_FONT: &str = "Bisasam_20x20.png";
 embedded_resource!(UI_FONT, "../resources/Bisasam_20x20.png");
 
 fn main() -> BError {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
     link_resource!(UI_FONT, format!("resources/{}", TERM_UI_FONT));
 
     let ctx = BTermBuilder::new()
@@ -182,6 +450,21 @@ fn main() -> BError {
     gs.rsrc.insert(RunState::MainMenu {
         menu_selection: MainMenuSelection::NewPlayerGame,
     });
+    match LevelPacks::load() {
+        Ok(packs) => gs.rsrc.insert(packs),
+        Err(error) => {
+            gs.rsrc.insert(LevelPacks::empty());
+            gs.rsrc.insert(error);
+            gs.rsrc.insert(RunState::LevelError);
+        }
+    }
+    let (progress, message) = match progress::Progress::load() {
+        Ok(progress) => (progress, gui::UiMessage::default()),
+        Err(err) => (progress::Progress::not_loaded(), gui::UiMessage(err)),
+    };
+    gs.rsrc.insert(progress);
     gs.rsrc.insert(map::Map::empty());
+    gs.rsrc.insert(level::LevelInfo::default());
+    gs.rsrc.insert(message);
     main_loop(ctx, gs)
 }
@@ -3,21 +3,21 @@ use crate::{
         Activable, ActivationKind, Block, BlocksLaser, BlocksTile, Cardinal, Movable, Position,
         ReflectsLaser,
     },
-    glyphs::*,
+    pack::LevelRef,
 };
-use bracket_lib::prelude::*;
 use legion::prelude::*;
 use legion::systems::SubWorld;
+use std::collections::VecDeque;
 use std::slice::Iter;
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum TileType {
     Wall,
     Floor,
     Exit,
 }
 pub struct Map {
-    pub level: u64,
+    pub level: Option<LevelRef>,
     tiles: Vec<TileType>,
     blocked_tiles: Vec<bool>,
     content_tiles: Vec<Vec<Entity>>,
@@ -29,7 +29,7 @@ pub struct Map {
 impl Map {
     pub fn empty() -> Map {
         Map {
-            level: 0,
+            level: None,
             tiles: vec![],
             blocked_tiles: vec![],
             content_tiles: vec![],
@@ -39,7 +39,7 @@ impl Map {
             height: 0,
         }
     }
-    pub fn new(level: u64, width: i32, height: i32) -> Map {
+    pub fn new(level: Option<LevelRef>, width: i32, height: i32) -> Map {
         let mut map = Map {
             level,
             tiles: vec![TileType::Floor; (width * height) as usize],
@@ -61,6 +61,9 @@ impl Map {
         }
         map
     }
+    pub fn tiletype(&self, x: i32, y: i32) -> TileType {
+        self.tiles[self.xy_idx(x, y)]
+    }
     pub fn is_exit(&self, x: i32, y: i32) -> bool {
         let idx = self.xy_idx(x, y);
         self.tiles[idx] == TileType::Exit
@@ -68,6 +71,7 @@ impl Map {
     pub fn set_tiletype(&mut self, x: i32, y: i32, tiletype: TileType) {
         let idx = self.xy_idx(x, y);
         self.tiles[idx] = tiletype;
+        self.exit_tiles.retain(|&exit| exit != idx);
         if tiletype == TileType::Exit {
             self.exit_tiles.push(idx);
         }
@@ -159,33 +163,10 @@ impl Map {
     fn idx_xy(&self, idx: usize) -> (i32, i32) {
         (idx as i32 % self.width, idx as i32 / self.width)
     }
-    pub fn draw(&self, ctx: &mut BTerm, start_x: i32, start_y: i32) {
-        for (idx, tile) in self.tiles.iter().enumerate() {
-            let (x, y) = self.idx_xy(idx);
-            // Render a tile depending upon the tile type
-            match tile {
-                TileType::Floor => {
-                    let glyph = if self.lasered_tiles[idx].contains(&Cardinal::N) {
-                        if self.lasered_tiles[idx].contains(&Cardinal::E) {
-                            LASERED_NS_EW
-                        } else {
-                            LASERED_NS
-                        }
-                    } else if self.lasered_tiles[idx].contains(&Cardinal::E) {
-                        LASERED_EW
-                    } else {
-                        FLOOR
-                    };
-                    ctx.set(start_x + x, start_y + y, GRAY, BLACK, glyph);
-                }
-                TileType::Wall => {
-                    ctx.set(start_x + x, start_y + y, BLUE_VIOLET, BLACK, WALL);
-                }
-                TileType::Exit => {
-                    ctx.set(start_x + x, start_y + y, CYAN, BLACK, EXIT);
-                }
-            }
-        }
+    /// Laser directions crossing the tile, N for vertical and E for horizontal.
+    pub fn lasered_directions(&self, x: i32, y: i32) -> &[Cardinal] {
+        let idx = self.xy_idx(x, y);
+        &self.lasered_tiles[idx]
     }
     /// True if the tile is not blocked and is not lasered.
     /// Allow AI to choose meaningfull movements
@@ -201,7 +182,7 @@ impl Map {
             .map(|&idx| self.idx_xy(idx))
             .collect::<Vec<_>>()
     }
-    ///
+    /// Shortest path from one tile to another, through tiles that are neither blocked nor lasered.
     pub fn try_go_to(&self, from: (i32, i32), to: (i32, i32)) -> Option<Vec<Cardinal>> {
         if from == to {
             return Some(vec![]);
@@ -211,33 +192,67 @@ impl Map {
         }
         let start = self.xy_idx(from.0, from.1);
         let end = self.xy_idx(to.0, to.1);
-        let res = a_star_search(start, end, self);
-        if !res.success {
-            return None;
-        };
+        let mut came_from: Vec<Option<(usize, Cardinal)>> = vec![None; self.tiles.len()];
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(idx) = queue.pop_front() {
+            if idx == end {
+                break;
+            }
+            let (x, y) = self.idx_xy(idx);
+            for &(direction, dx, dy) in [
+                (Cardinal::W, -1, 0),
+                (Cardinal::E, 1, 0),
+                (Cardinal::N, 0, -1),
+                (Cardinal::S, 0, 1),
+            ]
+            .iter()
+            {
+                if !self.is_exit_valid(x + dx, y + dy) {
+                    continue;
+                }
+                let next = self.xy_idx(x + dx, y + dy);
+                if next != start && came_from[next].is_none() {
+                    came_from[next] = Some((idx, direction));
+                    queue.push_back(next);
+                }
+            }
+        }
         let mut directions = vec![];
-        for (&idx1, &idx2) in res.steps.iter().zip(res.steps[1..].iter()) {
-            directions.push(match idx2 as i32 - idx1 as i32 {
-                1 => Cardinal::E,
-                -1 => Cardinal::W,
-                x if x == -self.width => Cardinal::N,
-                x if x == self.width => Cardinal::S,
-                _ => panic!(format!("Unexpected diff between idxes {} {}", idx1, idx2)),
-            });
+        let mut idx = end;
+        while idx != start {
+            let (previous, direction) = came_from[idx]?;
+            directions.push(direction);
+            idx = previous;
         }
+        directions.reverse();
         Some(directions)
     }
     pub fn can_go_to(&self, from: (i32, i32), to: (i32, i32)) -> bool {
-        if from == to {
-            return true;
-        }
-        if self.is_blocked(to.0, to.1) || self.is_lasered(to.0, to.1) {
-            return false;
-        }
+        self.try_go_to(from, to).is_some()
+    }
+    /// Smallest tile index the player can walk to from the tile, the same for every tile of a region
+    /// of tiles that are neither blocked nor lasered.
+    pub fn region_min_idx(&self, from: (i32, i32)) -> usize {
         let start = self.xy_idx(from.0, from.1);
-        let end = self.xy_idx(to.0, to.1);
-        let res = a_star_search(start, end, self);
-        res.success
+        let mut visited = vec![false; self.tiles.len()];
+        visited[start] = true;
+        let mut stack = vec![start];
+        let mut min = start;
+        while let Some(idx) = stack.pop() {
+            min = min.min(idx);
+            let (x, y) = self.idx_xy(idx);
+            for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                if self.is_exit_valid(x + dx, y + dy) {
+                    let next = self.xy_idx(x + dx, y + dy);
+                    if !visited[next] {
+                        visited[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        min
     }
     pub fn has_plate(&self, x: i32, y: i32, ecs: &World) -> bool {
         let idx = self.xy_idx(x, y);
@@ -289,38 +304,3 @@ impl Map {
         false
     }
 }
-impl BaseMap for Map {
-    fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
-        let mut exits = SmallVec::new();
-        let x = (idx % self.width as usize) as i32;
-        let y = (idx / self.width as usize) as i32;
-        let w = self.width as usize;
-
-        // Cardinal directions
-        if self.is_exit_valid(x - 1, y) {
-            exits.push((idx - 1, 1.0))
-        };
-        if self.is_exit_valid(x + 1, y) {
-            exits.push((idx + 1, 1.0))
-        };
-        if self.is_exit_valid(x, y - 1) {
-            exits.push((idx - w, 1.0))
-        };
-        if self.is_exit_valid(x, y + 1) {
-            exits.push((idx + w, 1.0))
-        };
-        exits
-    }
-
-    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
-        let w = self.width as usize;
-        let p1 = Point::new(idx1 % w, idx1 / w);
-        let p2 = Point::new(idx2 % w, idx2 / w);
-        DistanceAlg::Manhattan.distance2d(p1, p2)
-    }
-}
-impl Algorithm2D for Map {
-    fn dimensions(&self) -> Point {
-        Point::new(self.width, self.height)
-    }
-}
@@ -0,0 +1,143 @@
+use crate::assets::{self, PACKS_DIR};
+use crate::level::{LevelError, LevelErrorKind};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const MAIN_PACK: &str = "main";
+pub const AI_TESTS_PACK: &str = "ai_tests";
+
+/// A level inside a pack, as listed in the pack manifest.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PackLevel {
+    pub id: String,
+    pub title: String,
+    /// Path of the level file, relative to the manifest directory.
+    pub file: String,
+}
+
+/// Ordered list of levels, loaded from a `.toml` manifest in the assets.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LevelPack {
+    pub name: String,
+    pub title: String,
+    pub levels: Vec<PackLevel>,
+    #[serde(skip)]
+    dir: String,
+}
+impl LevelPack {
+    pub fn new(name: &str, title: &str, levels: Vec<PackLevel>) -> LevelPack {
+        LevelPack {
+            name: String::from(name),
+            title: String::from(title),
+            levels,
+            dir: String::new(),
+        }
+    }
+    pub fn load(file: &str) -> Result<LevelPack, LevelError> {
+        let content = assets::read(file).map_err(|err| LevelError {
+            file: String::from(file),
+            line: 0,
+            column: 0,
+            kind: LevelErrorKind::Io(err.to_string()),
+        })?;
+        LevelPack::parse(file, &content)
+    }
+    /// Parse the content of a manifest, file is its path used to find the level files.
+    pub fn parse(file: &str, content: &str) -> Result<LevelPack, LevelError> {
+        let mut pack = toml::from_str::<LevelPack>(content).map_err(|err| LevelError {
+            file: String::from(file),
+            line: 0,
+            column: 0,
+            kind: LevelErrorKind::InvalidManifest(err.to_string()),
+        })?;
+        pack.dir = String::from(assets::parent(file));
+        Ok(pack)
+    }
+    /// Path of the level file in the assets.
+    pub fn level_path(&self, index: usize) -> Option<String> {
+        self.levels
+            .get(index)
+            .map(|level| assets::join(&self.dir, &level.file))
+    }
+}
+
+/// Identifies a level as its position in one of the loaded packs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelRef {
+    pub pack: usize,
+    pub index: usize,
+}
+impl fmt::Display for LevelRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.index + 1)
+    }
+}
+
+/// All the packs available to the game.
+pub struct LevelPacks {
+    pub packs: Vec<LevelPack>,
+}
+impl LevelPacks {
+    /// Load every pack manifest of the assets, sorted by file name.
+    pub fn load() -> Result<LevelPacks, LevelError> {
+        let mut packs = vec![];
+        for file in assets::list_packs().iter() {
+            packs.push(LevelPack::load(file)?);
+        }
+        Ok(LevelPacks { packs })
+    }
+    pub fn empty() -> LevelPacks {
+        LevelPacks { packs: vec![] }
+    }
+    /// Reference to the first level of the pack with the given name.
+    pub fn first_level(&self, name: &str) -> Result<LevelRef, LevelError> {
+        match self.packs.iter().position(|pack| pack.name == name) {
+            Some(pack) if !self.packs[pack].levels.is_empty() => Ok(LevelRef { pack, index: 0 }),
+            _ => Err(LevelError {
+                file: String::from(PACKS_DIR),
+                line: 0,
+                column: 0,
+                kind: LevelErrorKind::MissingPack(String::from(name)),
+            }),
+        }
+    }
+    /// Reference to the level following this one in its pack, None at the end of the pack.
+    pub fn next(&self, level: LevelRef) -> Option<LevelRef> {
+        let pack = self.packs.get(level.pack)?;
+        if level.index + 1 < pack.levels.len() {
+            Some(LevelRef {
+                pack: level.pack,
+                index: level.index + 1,
+            })
+        } else {
+            None
+        }
+    }
+    pub fn get(&self, level: LevelRef) -> Option<&PackLevel> {
+        self.packs
+            .get(level.pack)
+            .and_then(|pack| pack.levels.get(level.index))
+    }
+    /// Key identifying a level across runs, as `pack/id`.
+    pub fn key(&self, level: LevelRef) -> Option<String> {
+        let pack = self.packs.get(level.pack)?;
+        let pack_level = pack.levels.get(level.index)?;
+        Some(format!("{}/{}", pack.name, pack_level.id))
+    }
+    /// Reference to the level with the given `pack/id` key.
+    pub fn find(&self, key: &str) -> Option<LevelRef> {
+        let pos = key.find('/')?;
+        let (name, id) = (&key[..pos], &key[pos + 1..]);
+        let pack = self.packs.iter().position(|pack| pack.name == name)?;
+        let index = self.packs[pack]
+            .levels
+            .iter()
+            .position(|pack_level| pack_level.id == id)?;
+        Some(LevelRef { pack, index })
+    }
+    pub fn level_path(&self, level: LevelRef) -> Option<String> {
+        self.packs
+            .get(level.pack)
+            .and_then(|pack| pack.level_path(level.index))
+    }
+}
@@ -1,14 +1,52 @@
-use super::State;
 use crate::components::{Actuator, Cardinal, Player, Position};
 use crate::map;
-use crate::{
-    gui::MainMenuSelection,
-    turn_history::{Action, TurnsHistory},
-    RunState,
-};
-use bracket_lib::prelude::*;
+use crate::turn_history::Action;
 use legion::prelude::*;
 
+/// Input of the player for one turn, as recorded in replays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerInput {
+    Move(Cardinal),
+    Actuate,
+}
+impl PlayerInput {
+    pub fn to_char(&self) -> char {
+        match self {
+            PlayerInput::Move(Cardinal::W) => 'L',
+            PlayerInput::Move(Cardinal::E) => 'R',
+            PlayerInput::Move(Cardinal::N) => 'U',
+            PlayerInput::Move(_) => 'D',
+            PlayerInput::Actuate => 'A',
+        }
+    }
+    pub fn from_char(c: char) -> Option<PlayerInput> {
+        match c {
+            'L' => Some(PlayerInput::Move(Cardinal::W)),
+            'R' => Some(PlayerInput::Move(Cardinal::E)),
+            'U' => Some(PlayerInput::Move(Cardinal::N)),
+            'D' => Some(PlayerInput::Move(Cardinal::S)),
+            'A' => Some(PlayerInput::Actuate),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a move string such as `LLURRA`, whitespace is ignored.
+pub fn parse_inputs(txt: &str) -> Result<Vec<PlayerInput>, String> {
+    txt.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| PlayerInput::from_char(c).ok_or_else(|| format!("Invalid input '{}'", c)))
+        .collect()
+}
+
+/// Actions resulting from the input, empty if it has no effect.
+pub fn try_input(input: PlayerInput, ecs: &mut World, rsrc: &mut Resources) -> Vec<Action> {
+    match input {
+        PlayerInput::Move(direction) => try_move_player(direction, ecs, rsrc),
+        PlayerInput::Actuate => try_actuate(ecs, rsrc),
+    }
+}
+
 pub fn try_move_player(direction: Cardinal, ecs: &mut World, rsrc: &mut Resources) -> Vec<Action> {
     let (delta_x, delta_y) = match direction {
         Cardinal::E => (1, 0),
@@ -90,43 +128,3 @@ pub fn try_actuate(ecs: &mut World, rsrc: &mut Resources) -> Vec<Action> {
     }
     actions
 }
-
-pub fn game_turn_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
-    let actions;
-    match ctx.key {
-        None => {
-            return RunState::GameAwaitingInput;
-        }
-        Some(key) => match key {
-            VirtualKeyCode::Left => {
-                actions = try_move_player(Cardinal::W, &mut gs.ecs, &mut gs.rsrc)
-            }
-            VirtualKeyCode::Right => {
-                actions = try_move_player(Cardinal::E, &mut gs.ecs, &mut gs.rsrc)
-            }
-            VirtualKeyCode::Up => actions = try_move_player(Cardinal::N, &mut gs.ecs, &mut gs.rsrc),
-            VirtualKeyCode::Down => {
-                actions = try_move_player(Cardinal::S, &mut gs.ecs, &mut gs.rsrc)
-            }
-            VirtualKeyCode::Space => actions = try_actuate(&mut gs.ecs, &mut gs.rsrc),
-            VirtualKeyCode::Back => {
-                let mut turn_history = gs.rsrc.get_mut::<TurnsHistory>().unwrap();
-                turn_history.undo_last_turn(&mut gs.ecs);
-                actions = vec![];
-            }
-            VirtualKeyCode::Escape => {
-                return RunState::MainMenu {
-                    menu_selection: MainMenuSelection::Continue,
-                }
-            }
-            _ => {
-                return RunState::GameAwaitingInput;
-            }
-        },
-    }
-    if actions.len() > 0 {
-        let mut turn_history = gs.rsrc.get_mut::<TurnsHistory>().unwrap();
-        turn_history.play_turn(&mut gs.ecs, actions);
-    }
-    RunState::GameTurn
-}
@@ -0,0 +1,109 @@
+//! Progress of the player through the packs, saved in the user data directory.
+//! Levels are identified by their `pack/id` key, so progress survives packs being reordered.
+use crate::assets;
+use crate::pack::{LevelPacks, LevelRef};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const PROGRESS_FILE: &str = "progress.toml";
+
+/// Best scores of a solved level, each one can come from a different solve.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct LevelRecord {
+    pub best_steps: i32,
+    pub best_energy: i32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Progress {
+    /// Last level played, resumed by Continue
+    pub current: Option<String>,
+    /// Levels unlocked besides the first level of each pack
+    #[serde(default)]
+    pub unlocked: Vec<String>,
+    #[serde(default)]
+    pub records: BTreeMap<String, LevelRecord>,
+    /// The save file exists but could not be read, it is then never overwritten
+    #[serde(skip)]
+    load_failed: bool,
+}
+impl Progress {
+    fn path() -> Option<PathBuf> {
+        assets::user_data_dir().map(|dir| dir.join(PROGRESS_FILE))
+    }
+    /// Saved progress, empty when there is none yet. A save file that cannot be read or parsed
+    /// is an error, the caller can go on with Progress::not_loaded.
+    pub fn load() -> Result<Progress, String> {
+        let path = match Progress::path() {
+            Some(path) => path,
+            None => return Ok(Progress::default()),
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Progress::default()),
+            Err(err) => return Err(format!("Cannot read progress {}: {}", path.display(), err)),
+        };
+        toml::from_str(&content)
+            .map_err(|err| format!("Invalid progress {}: {}", path.display(), err))
+    }
+    /// Empty progress used when the save file could not be loaded, it is never saved
+    /// so that the file is kept as it is.
+    pub fn not_loaded() -> Progress {
+        Progress {
+            load_failed: true,
+            ..Progress::default()
+        }
+    }
+    pub fn save(&self) -> io::Result<()> {
+        if self.load_failed {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the save file could not be loaded, it is not overwritten",
+            ));
+        }
+        let path = Progress::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No user data directory"))?;
+        let content = toml::to_string(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, content)
+    }
+    /// The first level of each pack is always unlocked, the others once the previous one is solved.
+    pub fn is_unlocked(&self, packs: &LevelPacks, level: LevelRef) -> bool {
+        level.index == 0
+            || packs
+                .key(level)
+                .map_or(false, |key| self.unlocked.contains(&key))
+    }
+    pub fn record(&self, packs: &LevelPacks, level: LevelRef) -> Option<LevelRecord> {
+        packs
+            .key(level)
+            .and_then(|key| self.records.get(&key).copied())
+    }
+    /// Level resumed by Continue, if it still exists in the packs.
+    pub fn current_level(&self, packs: &LevelPacks) -> Option<LevelRef> {
+        self.current.as_ref().and_then(|key| packs.find(key))
+    }
+    pub fn set_current(&mut self, packs: &LevelPacks, level: LevelRef) {
+        self.current = packs.key(level);
+    }
+    /// Keep the best scores of the level and unlock the following one.
+    pub fn solved(&mut self, packs: &LevelPacks, level: LevelRef, steps: i32, energy: i32) {
+        if let Some(key) = packs.key(level) {
+            let record = self.records.entry(key).or_insert(LevelRecord {
+                best_steps: steps,
+                best_energy: energy,
+            });
+            record.best_steps = record.best_steps.min(steps);
+            record.best_energy = record.best_energy.min(energy);
+        }
+        if let Some(next) = packs.next(level).and_then(|next| packs.key(next)) {
+            if !self.unlocked.contains(&next) {
+                self.unlocked.push(next);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Presentation of a level: glyphs and colours are derived here from the game state,
+//! the simulation itself does not know about them.
+use crate::components::{
+    Activable, ActivationKind, Block, Cardinal, Door, Laser, Player, Position, ReflectsLaser,
+};
+use crate::glyphs::*;
+use crate::map::{Map, TileType};
+use crate::turn_history::{TurnState, TurnsHistory};
+use bracket_lib::prelude::*;
+use legion::prelude::*;
+
+/// Draw the tiles of the map, with the lasers crossing them.
+pub fn draw_map(map: &Map, ctx: &mut BTerm, start_x: i32, start_y: i32) {
+    for y in 0..map.height {
+        for x in 0..map.width {
+            match map.tiletype(x, y) {
+                TileType::Floor => {
+                    let lasered = map.lasered_directions(x, y);
+                    let glyph = if lasered.contains(&Cardinal::N) {
+                        if lasered.contains(&Cardinal::E) {
+                            LASERED_NS_EW
+                        } else {
+                            LASERED_NS
+                        }
+                    } else if lasered.contains(&Cardinal::E) {
+                        LASERED_EW
+                    } else {
+                        FLOOR
+                    };
+                    ctx.set(start_x + x, start_y + y, GRAY, BLACK, glyph);
+                }
+                TileType::Wall => {
+                    ctx.set(start_x + x, start_y + y, BLUE_VIOLET, BLACK, WALL);
+                }
+                TileType::Exit => {
+                    ctx.set(start_x + x, start_y + y, CYAN, BLACK, EXIT);
+                }
+            }
+        }
+    }
+}
+
+/// Glyph, colour and render order of the entity, None if it is not drawn.
+/// Order 0 is in front, masking 1 which masks 2.
+fn appearance(ecs: &World, entity: Entity, dead: bool) -> Option<(FontCharType, RGB, i32)> {
+    if ecs.get_tag::<Player>(entity).is_some() {
+        let fg = if dead { BROWN1 } else { YELLOW };
+        return Some((PLAYER, RGB::named(fg), 0));
+    }
+    if ecs.get_tag::<Block>(entity).is_some() {
+        return Some((MOVABLE_BLOCK, RGB::named(ORANGE), 1));
+    }
+    if let Some(laser) = ecs.get_component::<Laser>(entity) {
+        let glyph = match laser.direction {
+            Cardinal::N => LASER_N,
+            Cardinal::E => LASER_E,
+            Cardinal::W => LASER_W,
+            _ => LASER_S,
+        };
+        return Some((glyph, RGB::named(LIGHT_BLUE), 1));
+    }
+    if let Some(reflector) = ecs.get_component::<ReflectsLaser>(entity) {
+        let glyph = if reflector.orientation == Cardinal::NE {
+            REFLECTOR_NE
+        } else {
+            REFLECTOR_NW
+        };
+        return Some((glyph, RGB::named(WHITE), 1));
+    }
+    if let Some(door) = ecs.get_component::<Door>(entity) {
+        if door.opened {
+            return None;
+        }
+        return Some((DOOR_H_CLOSED, RGB::named(RED), 1));
+    }
+    if let Some(activable) = ecs.get_component::<Activable>(entity) {
+        let glyph = match activable.kind {
+            ActivationKind::Weight => WEIGHT_PLATE,
+            ActivationKind::Laser => LASER_RECEPTOR,
+        };
+        let fg = if activable.active { GREEN } else { RED };
+        return Some((glyph, RGB::named(fg), 2));
+    }
+    None
+}
+
+/// Draw the map and the entities on it, centered horizontally.
+pub fn draw_level(ecs: &World, rsrc: &Resources, ctx: &mut BTerm, start_y: i32) {
+    let map = rsrc.get::<Map>().unwrap();
+    let dead = rsrc
+        .get::<TurnsHistory>()
+        .map_or(false, |history| history.state == TurnState::PlayerDead);
+    let start_x = (crate::TERM_WIDTH - map.width) / 2;
+    draw_map(&map, ctx, start_x, start_y);
+    let mut data = <(Read<Position>,)>::query()
+        .iter_entities(ecs)
+        .filter_map(|(entity, (pos,))| {
+            appearance(ecs, entity, dead).map(|(glyph, fg, order)| (*pos, glyph, fg, order))
+        })
+        .collect::<Vec<_>>();
+    data.sort_by(|d1, d2| d2.3.cmp(&d1.3));
+    for (pos, glyph, fg, _) in data.iter() {
+        ctx.set(
+            start_x + pos.x,
+            start_y + pos.y,
+            *fg,
+            RGB::named(BLACK),
+            *glyph,
+        );
+    }
+}
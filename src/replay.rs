@@ -0,0 +1,205 @@
+//! Replays of solved levels, recorded as the inputs of the player.
+//! They are written in the user data directory when a level is solved,
+//! and can be watched again from the level solved screen.
+use crate::assets;
+use crate::player::{parse_inputs, try_input, PlayerInput};
+use crate::turn_history::TurnsHistory;
+use crate::RunState;
+use bracket_lib::prelude::*;
+use legion::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const REPLAYS_DIR: &str = "replays";
+/// Replay speed in turns per second.
+const DEFAULT_SPEED: i32 = 4;
+const MAX_SPEED: i32 = 64;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Replay {
+    pub pack: String,
+    pub level: String,
+    /// Hash of the level file, as hexadecimal, a replay only applies to the version it was recorded on.
+    pub hash: String,
+    pub steps: i32,
+    pub energy: i32,
+    /// One character per turn: L, R, U or D to move, A to actuate.
+    pub inputs: String,
+}
+impl Replay {
+    /// Replay of the turns played in history, None if some turns are not player inputs.
+    pub fn record(pack: &str, level: &str, hash: u64, history: &TurnsHistory) -> Option<Replay> {
+        let inputs = history
+            .inputs
+            .iter()
+            .map(|input| input.map(|input| input.to_char()))
+            .collect::<Option<String>>()?;
+        Some(Replay {
+            pack: String::from(pack),
+            level: String::from(level),
+            hash: format!("{:016x}", hash),
+            steps: history.steps,
+            energy: history.energy_used,
+            inputs,
+        })
+    }
+    pub fn matches(&self, hash: u64) -> bool {
+        self.hash == format!("{:016x}", hash)
+    }
+    pub fn inputs(&self) -> Result<Vec<PlayerInput>, String> {
+        parse_inputs(&self.inputs)
+    }
+    /// File of the replay of a level, in the user data directory.
+    pub fn path(pack: &str, level: &str) -> Option<PathBuf> {
+        assets::user_data_dir().map(|dir| {
+            dir.join(REPLAYS_DIR)
+                .join(pack)
+                .join(format!("{}.toml", level))
+        })
+    }
+    pub fn load(pack: &str, level: &str) -> Result<Replay, String> {
+        let path = Replay::path(pack, level).ok_or("No user data directory")?;
+        let content =
+            fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        toml::from_str(&content).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+    pub fn save(&self) -> io::Result<PathBuf> {
+        let path = Replay::path(&self.pack, &self.level)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No user data directory"))?;
+        let content = toml::to_string(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, content)?;
+        Ok(path)
+    }
+}
+
+/// Plays the inputs of a replay back, instead of the player.
+pub struct ReplayPlayer {
+    inputs: Vec<PlayerInput>,
+    next: usize,
+    /// Turns per second
+    speed: i32,
+    /// Time since the last turn, in ms
+    elapsed: f32,
+    pub finished: bool,
+}
+impl ReplayPlayer {
+    pub fn new(replay: &Replay) -> Result<ReplayPlayer, String> {
+        Ok(ReplayPlayer {
+            inputs: replay.inputs()?,
+            next: 0,
+            speed: DEFAULT_SPEED,
+            elapsed: 0.0,
+            finished: false,
+        })
+    }
+    /// Play the next input when it is time to.
+    /// Keys +/- change the speed, Escape gives control back to the player.
+    pub fn play_next_turn(
+        &mut self,
+        ecs: &mut World,
+        rsrc: &mut Resources,
+        ctx: &mut BTerm,
+    ) -> RunState {
+        match ctx.key {
+            Some(VirtualKeyCode::Add) | Some(VirtualKeyCode::Equals) => {
+                self.speed = (self.speed * 2).min(MAX_SPEED)
+            }
+            Some(VirtualKeyCode::Subtract) | Some(VirtualKeyCode::Minus) => {
+                self.speed = (self.speed / 2).max(1)
+            }
+            Some(VirtualKeyCode::Escape) => {
+                self.finished = true;
+                return RunState::GameDraw;
+            }
+            _ => {}
+        }
+        if self.next >= self.inputs.len() {
+            self.finished = true;
+            return RunState::GameDraw;
+        }
+        self.elapsed += ctx.frame_time_ms;
+        if self.elapsed < 1000.0 / self.speed as f32 {
+            return RunState::GameDraw;
+        }
+        self.elapsed = 0.0;
+        let input = self.inputs[self.next];
+        self.next += 1;
+        let actions = try_input(input, ecs, rsrc);
+        if actions.len() > 0 {
+            let mut turn_history = rsrc.get_mut::<TurnsHistory>().unwrap();
+            turn_history.play_turn(ecs, Some(input), actions);
+        }
+        RunState::GameTurn
+    }
+    pub fn draw_state(&self, ctx: &mut BTerm) {
+        ctx.print_color(
+            1,
+            28,
+            RGB::named(CYAN),
+            RGB::named(BLACK),
+            format!(
+                "Replay {}/{} at {}/s (+/-, Esc)",
+                self.next,
+                self.inputs.len(),
+                self.speed
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Cardinal;
+    use crate::game_core::{verify, Game};
+    use crate::level::parse_level;
+    use crate::turn_history::TurnState;
+
+    #[test]
+    fn recorded_replay_plays_back_to_the_exit() {
+        let content = "\
+######
+#....#
+#..b.E
+#.@.b#
+######
+";
+        let description = parse_level("replay", content).unwrap_or_else(|err| panic!("{}", err));
+        let mut game = Game::with_rules(&description);
+        // An undone turn is not part of the replay
+        game.step(PlayerInput::Move(Cardinal::W));
+        game.rsrc
+            .get_mut::<TurnsHistory>()
+            .unwrap()
+            .undo_last_turn(&mut game.ecs);
+        game.run_systems();
+        game.run_systems();
+        for &input in parse_inputs("RURR").unwrap().iter() {
+            game.step(input);
+        }
+        assert_eq!(game.state(), TurnState::PlayerAtExit);
+        let replay = Replay::record(
+            "pack",
+            "level",
+            description.hash,
+            &game.rsrc.get::<TurnsHistory>().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(replay.inputs, "RURR");
+        assert_eq!((replay.steps, replay.energy), (4, 1));
+
+        let replay: Replay = toml::from_str(&toml::to_string(&replay).unwrap()).unwrap();
+        assert!(replay.matches(description.hash));
+        assert!(!replay.matches(description.hash ^ 1));
+        let verdict = verify(&description, &replay.inputs().unwrap());
+        assert_eq!(verdict.state, TurnState::PlayerAtExit);
+        assert_eq!(
+            (verdict.steps, verdict.energy),
+            (replay.steps, replay.energy)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Import of Sokoban levels in the XSB/SOK text format.
+//! Goals become weight plates, and an exit is opened in a border wall reachable by the player,
+//! so the level is solved by pushing every box on a goal and walking out.
+
+/// A level read from a Sokoban collection, rows are kept as in the file.
+pub struct SokobanLevel {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub rows: Vec<String>,
+}
+
+const BOARD_CHARS: &str = "#@+$*.-_ ";
+
+fn is_board_line(line: &str) -> bool {
+    let line = line.trim_end();
+    line.contains('#') && line.chars().all(|c| BOARD_CHARS.contains(c))
+}
+
+/// Split a collection file in its levels.
+/// A `Title:` line following a board names it, otherwise the comment or text line preceding it.
+pub fn parse_collection(content: &str) -> Vec<SokobanLevel> {
+    let mut levels: Vec<SokobanLevel> = vec![];
+    let mut pending_title = None;
+    let mut in_board = false;
+    for line in content.lines() {
+        if is_board_line(line) {
+            if !in_board {
+                levels.push(SokobanLevel {
+                    title: pending_title.take(),
+                    author: None,
+                    rows: vec![],
+                });
+                in_board = true;
+            }
+            levels
+                .last_mut()
+                .unwrap()
+                .rows
+                .push(String::from(line.trim_end()));
+            continue;
+        }
+        in_board = false;
+        let text = line.trim().trim_start_matches(';').trim();
+        if text.is_empty() {
+            continue;
+        }
+        let lower = text.to_lowercase();
+        if lower.starts_with("title:") {
+            let title = String::from(text["title:".len()..].trim());
+            match levels.last_mut() {
+                Some(level) if pending_title.is_none() => level.title = Some(title),
+                _ => pending_title = Some(title),
+            }
+        } else if lower.starts_with("author:") {
+            if let Some(level) = levels.last_mut() {
+                level.author = Some(String::from(text["author:".len()..].trim()));
+            }
+        } else if !text.contains(':') {
+            pending_title = Some(String::from(text));
+        }
+    }
+    levels
+}
+
+/// Convert a Sokoban level to the Griphus level text format.
+pub fn to_griphus(level: &SokobanLevel) -> Result<String, String> {
+    // Keep a ring of outside tiles around the board, it becomes the border wall.
+    let width = level
+        .rows
+        .iter()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let height = level.rows.len() + 2;
+    let mut grid = vec![vec![' '; width]; height];
+    for (y, row) in level.rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            grid[y + 1][x + 1] = match c {
+                '-' | '_' => ' ',
+                c => c,
+            };
+        }
+    }
+    // Everything connected to the outside ring without crossing a wall is outside the level.
+    let mut outside = vec![vec![false; width]; height];
+    let mut stack = vec![];
+    for x in 0..width {
+        stack.push((x, 0));
+        stack.push((x, height - 1));
+    }
+    for y in 0..height {
+        stack.push((0, y));
+        stack.push((width - 1, y));
+    }
+    while let Some((x, y)) = stack.pop() {
+        if outside[y][x] || grid[y][x] == '#' {
+            continue;
+        }
+        outside[y][x] = true;
+        for (nx, ny) in neighbours(x, y, width, height) {
+            stack.push((nx, ny));
+        }
+    }
+    let player = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .find(|&(x, y)| grid[y][x] == '@' || grid[y][x] == '+')
+        .ok_or_else(|| String::from("Level has no player"))?;
+    // The exit replaces the first wall of the outer border, in reading order, next to a tile
+    // the player can reach. Opening an inner wall would add a path and change the puzzle.
+    let on_border = |x: usize, y: usize| {
+        neighbours(x, y, width, height)
+            .into_iter()
+            .any(|(nx, ny)| outside[ny][nx])
+    };
+    let mut reachable = vec![vec![false; width]; height];
+    let mut stack = vec![player];
+    let mut exit = None;
+    while let Some((x, y)) = stack.pop() {
+        if reachable[y][x] {
+            continue;
+        }
+        reachable[y][x] = true;
+        for (nx, ny) in neighbours(x, y, width, height) {
+            if grid[ny][nx] == '#' {
+                if on_border(nx, ny) && exit.map_or(true, |(ex, ey)| (ny, nx) < (ey, ex)) {
+                    exit = Some((nx, ny));
+                }
+            } else {
+                stack.push((nx, ny));
+            }
+        }
+    }
+    let exit = exit.ok_or_else(|| {
+        String::from("Level has no border wall next to the player to put the exit in")
+    })?;
+    let mut plates = vec![];
+    let mut content = String::new();
+    if let Some(title) = level.title.as_ref() {
+        content.push_str(&format!("title: {}\n", title));
+    }
+    if let Some(author) = level.author.as_ref() {
+        content.push_str(&format!("author: {}\n", author));
+    }
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    for y in 0..height {
+        for x in 0..width {
+            let c = if (x, y) == exit {
+                'E'
+            } else if outside[y][x] {
+                '#'
+            } else {
+                match grid[y][x] {
+                    '#' => '#',
+                    ' ' => '.',
+                    '$' => 'b',
+                    '.' => 'x',
+                    '*' => '*',
+                    '@' => '@',
+                    '+' => {
+                        plates.push((x, y));
+                        '@'
+                    }
+                    c => return Err(format!("Unexpected character '{}'", c)),
+                }
+            };
+            content.push(c);
+        }
+        content.push('\n');
+    }
+    if !plates.is_empty() {
+        content.push('\n');
+        for (i, (x, y)) in plates.iter().enumerate() {
+            content.push_str(&format!("P{}: plate({},{})\n", i + 1, x, y));
+        }
+    }
+    Ok(content)
+}
+
+fn neighbours(x: usize, y: usize, width: usize, height: usize) -> Vec<(usize, usize)> {
+    let mut result = vec![];
+    if x > 0 {
+        result.push((x - 1, y));
+    }
+    if x + 1 < width {
+        result.push((x + 1, y));
+    }
+    if y > 0 {
+        result.push((x, y - 1));
+    }
+    if y + 1 < height {
+        result.push((x, y + 1));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{parse_level, LevelEntity};
+
+    #[test]
+    fn goals_boxes_and_player_on_goal_are_converted() {
+        let level = SokobanLevel {
+            title: Some(String::from("Corner")),
+            author: Some(String::from("Tester")),
+            rows: vec![
+                String::from("  ####"),
+                String::from("###  #"),
+                String::from("# $$ #"),
+                String::from("# .*+#"),
+                String::from("######"),
+            ],
+        };
+        let content = to_griphus(&level).unwrap();
+        assert_eq!(
+            content,
+            "\
+title: Corner
+author: Tester
+
+########
+####E###
+####..##
+##.bb.##
+##.x*@##
+########
+########
+
+P1: plate(5,4)
+"
+        );
+        let description = parse_level("corner.txt", &content).unwrap();
+        assert!(description
+            .entities
+            .contains(&(5, 4, LevelEntity::WeightPlate)));
+        assert!(description.entities.contains(&(5, 4, LevelEntity::Player)));
+    }
+
+    #[test]
+    fn collection_is_split_on_blank_and_title_lines() {
+        let content = "\
+Level one
+#####
+#@$.#
+#####
+
+#####
+#.$@#
+#####
+Title: Second
+Author: Someone
+######
+#@ $.#
+######
+Title: Third
+";
+        let levels = parse_collection(content);
+        assert_eq!(levels.len(), 3);
+        let titles: Vec<_> = levels.iter().map(|level| level.title.as_deref()).collect();
+        assert_eq!(
+            titles,
+            vec![Some("Level one"), Some("Second"), Some("Third")]
+        );
+        let authors: Vec<_> = levels.iter().map(|level| level.author.as_deref()).collect();
+        assert_eq!(authors, vec![None, Some("Someone"), None]);
+        assert_eq!(levels[0].rows, vec!["#####", "#@$.#", "#####"]);
+        assert_eq!(levels[1].rows, vec!["#####", "#.$@#", "#####"]);
+        assert_eq!(levels[2].rows, vec!["######", "#@ $.#", "######"]);
+    }
+}
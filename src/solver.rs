@@ -0,0 +1,454 @@
+//! Solvers searching the cheapest solution of a level on GameState, exploring states
+//! cheapest first so the first solution found is minimal for the metric when no state was dropped,
+//! and the trade-offs between steps and energy.
+use crate::components::Cardinal;
+use crate::game_state::{GameState, Rules};
+use crate::map::TileType;
+use crate::player::PlayerInput;
+use crate::turn_history::TurnState;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+const DIRECTIONS: [Cardinal; 4] = [Cardinal::N, Cardinal::S, Cardinal::W, Cardinal::E];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    /// Fewest turns, then fewest pushes
+    Moves,
+    /// Fewest blocks, lasers and reflectors pushed, then fewest turns
+    Pushes,
+    /// Least energy used by pushes and actuations, then fewest turns
+    Energy,
+}
+impl Metric {
+    pub fn name(&self) -> &str {
+        match self {
+            Metric::Moves => "moves",
+            Metric::Pushes => "pushes",
+            Metric::Energy => "energy",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Solution {
+    pub inputs: Vec<PlayerInput>,
+    pub steps: i32,
+    pub pushes: i32,
+    pub energy: i32,
+}
+impl Solution {
+    /// Move string such as `LLURRA`, as read by verify.
+    pub fn moves(&self) -> String {
+        self.inputs.iter().map(|input| input.to_char()).collect()
+    }
+}
+
+pub struct Search {
+    pub solution: Option<Solution>,
+    /// States whose successors were explored
+    pub explored: usize,
+    /// Successors skipped because their state was already reached as cheaply
+    pub duplicates: usize,
+    /// Explored states without any successor
+    pub dead_ends: usize,
+    /// States remembered when the search ended
+    pub states: usize,
+    /// False when the search stopped at max_states or the timeout before exploring every state
+    pub complete: bool,
+}
+
+/// A state reached in the search, and how it was reached from its parent.
+struct Node {
+    state: GameState,
+    parent: usize,
+    inputs: Vec<PlayerInput>,
+    steps: i32,
+    pushes: i32,
+    energy: i32,
+}
+
+/// Search the solution of the level that is minimal for the metric, remembering at most
+/// max_states states and giving up after the timeout. A solution found after states were dropped
+/// at max_states, complete being false, may not be minimal.
+///
+/// Pushes and energy are searched with the actions of the AI: walk to a movable and push it,
+/// walk to a reflector and turn it, or walk to an exit. States are then identified by the region
+/// the player can walk in rather than its exact position. Walks take the shortest path, so the
+/// count of pushes or the energy is minimal, but waiting for a timer to expire is not considered.
+pub fn solve(
+    rules: &Rules,
+    start: &GameState,
+    metric: Metric,
+    max_states: usize,
+    timeout: Option<Duration>,
+) -> Search {
+    let start_time = Instant::now();
+    let key = |state: &GameState| match metric {
+        Metric::Moves => state.clone(),
+        Metric::Pushes | Metric::Energy => canonical(rules, state),
+    };
+    let cost = |node: &Node| match metric {
+        Metric::Moves => (node.steps, node.pushes),
+        Metric::Pushes => (node.pushes, node.steps),
+        Metric::Energy => (node.energy, node.steps),
+    };
+    let mut nodes = vec![Node {
+        state: start.clone(),
+        parent: 0,
+        inputs: vec![],
+        steps: 0,
+        pushes: 0,
+        energy: 0,
+    }];
+    let mut best = HashMap::new();
+    best.insert(key(start), cost(&nodes[0]));
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse((cost(&nodes[0]), 0)));
+    let mut explored = 0;
+    let mut duplicates = 0;
+    let mut dead_ends = 0;
+    let mut complete = true;
+    while let Some(Reverse((node_cost, id))) = queue.pop() {
+        if timeout.map_or(false, |timeout| start_time.elapsed() >= timeout) {
+            complete = false;
+            break;
+        }
+        if best
+            .get(&key(&nodes[id].state))
+            .map_or(false, |&best| best < node_cost)
+        {
+            continue;
+        }
+        if nodes[id].state.state == TurnState::PlayerAtExit {
+            return Search {
+                solution: Some(solution(&nodes, id)),
+                explored,
+                duplicates,
+                dead_ends,
+                states: best.len(),
+                complete,
+            };
+        }
+        explored += 1;
+        let successors = match metric {
+            Metric::Moves => single_inputs(rules, &nodes[id].state),
+            Metric::Pushes | Metric::Energy => ai_actions(rules, &nodes[id].state)
+                .into_iter()
+                .filter_map(|inputs| play(rules, &nodes[id].state, inputs))
+                .collect(),
+        };
+        if successors.is_empty() {
+            dead_ends += 1;
+        }
+        for (inputs, state, pushes, energy) in successors {
+            let node = Node {
+                parent: id,
+                steps: nodes[id].steps + inputs.len() as i32,
+                pushes: nodes[id].pushes + pushes,
+                energy: nodes[id].energy + energy,
+                inputs,
+                state,
+            };
+            let node_key = key(&node.state);
+            let node_cost = cost(&node);
+            match best.get(&node_key) {
+                Some(&best) if best <= node_cost => {
+                    duplicates += 1;
+                    continue;
+                }
+                None if best.len() >= max_states => {
+                    complete = false;
+                    continue;
+                }
+                _ => {}
+            }
+            best.insert(node_key, node_cost);
+            queue.push(Reverse((node_cost, nodes.len())));
+            nodes.push(node);
+        }
+    }
+    Search {
+        solution: None,
+        explored,
+        duplicates,
+        dead_ends,
+        states: best.len(),
+        complete,
+    }
+}
+
+/// Solutions of a level where no other one takes fewer steps and less energy.
+pub struct Front {
+    /// By increasing steps and decreasing energy
+    pub solutions: Vec<Solution>,
+    /// States whose successors were explored
+    pub explored: usize,
+    /// False when the search stopped at max_states, some solutions may then be missing
+    pub complete: bool,
+}
+
+/// Search the Pareto front of the (steps, energy) solutions of the level, remembering at most
+/// max_states states.
+///
+/// Labels are explored by increasing steps then energy, so a label reaching a state is only kept
+/// when it uses less energy than every label that reached it before, and a solution is on the
+/// front when it uses less energy than the previous one.
+pub fn pareto_front(rules: &Rules, start: &GameState, max_states: usize) -> Front {
+    let mut nodes = vec![Node {
+        state: start.clone(),
+        parent: 0,
+        inputs: vec![],
+        steps: 0,
+        pushes: 0,
+        energy: 0,
+    }];
+    // Least energy of the labels reaching each state
+    let mut least = HashMap::new();
+    least.insert(start.clone(), 0);
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse((0, 0, 0)));
+    let mut solutions: Vec<Solution> = vec![];
+    let mut explored = 0;
+    let mut complete = true;
+    while let Some(Reverse((_, energy, id))) = queue.pop() {
+        // Nothing can be on the front once it uses as much energy as the last solution
+        if solutions.last().map_or(false, |last| last.energy <= energy)
+            || least[&nodes[id].state] < energy
+        {
+            continue;
+        }
+        if nodes[id].state.state == TurnState::PlayerAtExit {
+            solutions.push(solution(&nodes, id));
+            continue;
+        }
+        explored += 1;
+        for (inputs, state, pushes, used) in single_inputs(rules, &nodes[id].state) {
+            let node = Node {
+                parent: id,
+                steps: nodes[id].steps + 1,
+                pushes: nodes[id].pushes + pushes,
+                energy: nodes[id].energy + used,
+                inputs,
+                state,
+            };
+            match least.get(&node.state) {
+                Some(&least) if least <= node.energy => continue,
+                None if least.len() >= max_states => {
+                    complete = false;
+                    continue;
+                }
+                _ => {}
+            }
+            least.insert(node.state.clone(), node.energy);
+            queue.push(Reverse((node.steps, node.energy, nodes.len())));
+            nodes.push(node);
+        }
+    }
+    Front {
+        solutions,
+        explored,
+        complete,
+    }
+}
+
+fn solution(nodes: &[Node], id: usize) -> Solution {
+    let mut parts = vec![];
+    let mut cur = id;
+    while cur != 0 {
+        parts.push(&nodes[cur].inputs);
+        cur = nodes[cur].parent;
+    }
+    Solution {
+        inputs: parts.into_iter().rev().flatten().copied().collect(),
+        steps: nodes[id].steps,
+        pushes: nodes[id].pushes,
+        energy: nodes[id].energy,
+    }
+}
+
+/// Every input the player can play, with the resulting state, pushes and energy.
+fn single_inputs(rules: &Rules, state: &GameState) -> Vec<(Vec<PlayerInput>, GameState, i32, i32)> {
+    DIRECTIONS
+        .iter()
+        .map(|&direction| PlayerInput::Move(direction))
+        .chain(std::iter::once(PlayerInput::Actuate))
+        .filter_map(|input| play(rules, state, vec![input]))
+        .collect()
+}
+
+/// Play the inputs one after the other, stopping early at the exit.
+/// None if one of them has no effect or the player dies.
+fn play(
+    rules: &Rules,
+    state: &GameState,
+    mut inputs: Vec<PlayerInput>,
+) -> Option<(Vec<PlayerInput>, GameState, i32, i32)> {
+    let mut state = state.clone();
+    let mut pushes = 0;
+    let mut energy = 0;
+    for i in 0..inputs.len() {
+        let (next, used) = state.step(rules, inputs[i])?;
+        if let PlayerInput::Move(_) = inputs[i] {
+            pushes += used;
+        }
+        energy += used;
+        state = next;
+        match state.state {
+            TurnState::PlayerDead => return None,
+            TurnState::PlayerAtExit => {
+                inputs.truncate(i + 1);
+                break;
+            }
+            TurnState::Running => {}
+        }
+    }
+    Some((inputs, state, pushes, energy))
+}
+
+/// Shortest walk from the player to every tile it can reach.
+fn walks(rules: &Rules, state: &GameState) -> Vec<Option<Vec<PlayerInput>>> {
+    let walkable = state.walkable(rules);
+    let mut walks = vec![None; rules.tiles.len()];
+    walks[state.player as usize] = Some(vec![]);
+    let mut queue = std::collections::VecDeque::new();
+    queue.push_back(state.player);
+    while let Some(idx) = queue.pop_front() {
+        for &direction in DIRECTIONS.iter() {
+            if let Some(next) = rules.neighbour(idx, direction.get_delta_xy()) {
+                if walkable[next as usize] && walks[next as usize].is_none() {
+                    let mut walk = walks[idx as usize].clone().unwrap();
+                    walk.push(PlayerInput::Move(direction));
+                    walks[next as usize] = Some(walk);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    walks
+}
+
+/// The state with the player moved to the smallest tile index it can walk to.
+fn canonical(rules: &Rules, state: &GameState) -> GameState {
+    let mut canonical = state.clone();
+    if let Some(idx) = walks(rules, state).iter().position(|walk| walk.is_some()) {
+        canonical.player = idx as u16;
+    }
+    canonical
+}
+
+/// Inputs playing the actions of the AI from the state: walking to an exit, pushing a movable
+/// or turning a reflector, as `AI::find_possible_actions` does on the World.
+fn ai_actions(rules: &Rules, state: &GameState) -> Vec<Vec<PlayerInput>> {
+    let walks = walks(rules, state);
+    let walk_to = |idx: u16, last: Option<PlayerInput>| {
+        walks[idx as usize].as_ref().map(|walk| {
+            let mut inputs = walk.clone();
+            inputs.extend(last);
+            inputs
+        })
+    };
+    let mut actions = vec![];
+    for (idx, &tile) in rules.tiles.iter().enumerate() {
+        if tile == TileType::Exit {
+            actions.extend(walk_to(idx as u16, None));
+        }
+    }
+    let movables = state
+        .blocks
+        .iter()
+        .copied()
+        .chain(state.lasers.iter().map(|&(idx, _)| idx))
+        .chain(state.reflectors.iter().map(|&(idx, _)| idx));
+    for movable in movables {
+        for &direction in DIRECTIONS.iter() {
+            let behind = rules.neighbour(movable, direction.inv().get_delta_xy());
+            actions.extend(
+                behind.and_then(|behind| walk_to(behind, Some(PlayerInput::Move(direction)))),
+            );
+        }
+    }
+    for &(reflector, _) in state.reflectors.iter() {
+        for &direction in DIRECTIONS.iter() {
+            let next_to = rules.neighbour(reflector, direction.get_delta_xy());
+            actions
+                .extend(next_to.and_then(|next_to| walk_to(next_to, Some(PlayerInput::Actuate))));
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::parse_level;
+
+    /// Pushing the block out of the way takes 4 turns, walking around it takes 6 turns.
+    const DETOUR: &str = "\
+######
+#....#
+#..b.E
+#.@.b#
+######
+";
+    const MAX_STATES: usize = 10_000;
+
+    fn detour() -> (Rules, GameState) {
+        let description = parse_level("detour", DETOUR).unwrap_or_else(|err| panic!("{}", err));
+        Rules::new(&description).unwrap()
+    }
+
+    /// Play the solution from the start, it must end at the exit.
+    fn assert_solves(rules: &Rules, start: &GameState, solution: &Solution) {
+        assert_eq!(solution.inputs.len() as i32, solution.steps);
+        let mut state = start.clone();
+        for &input in solution.inputs.iter() {
+            state = state.step(rules, input).unwrap().0;
+        }
+        assert_eq!(state.state, TurnState::PlayerAtExit, "{}", solution.moves());
+    }
+
+    #[test]
+    fn solutions_are_minimal_for_their_metric() {
+        let (rules, start) = detour();
+        let expected = [
+            (Metric::Moves, 4, 1, 1),
+            (Metric::Pushes, 6, 0, 0),
+            (Metric::Energy, 6, 0, 0),
+        ];
+        for &(metric, steps, pushes, energy) in expected.iter() {
+            let search = solve(&rules, &start, metric, MAX_STATES, None);
+            assert!(search.complete, "{}", metric.name());
+            let solution = search.solution.unwrap();
+            assert_solves(&rules, &start, &solution);
+            assert_eq!(
+                (solution.steps, solution.pushes, solution.energy),
+                (steps, pushes, energy),
+                "{}",
+                metric.name()
+            );
+        }
+    }
+
+    #[test]
+    fn pareto_front_only_keeps_non_dominated_solutions() {
+        let (rules, start) = detour();
+        let front = pareto_front(&rules, &start, MAX_STATES);
+        assert!(front.complete);
+        let costs: Vec<_> = front
+            .solutions
+            .iter()
+            .map(|solution| (solution.steps, solution.energy))
+            .collect();
+        assert_eq!(costs, vec![(4, 1), (6, 0)]);
+        for solution in front.solutions.iter() {
+            assert_solves(&rules, &start, solution);
+            assert!(!front.solutions.iter().any(|other| {
+                other.steps <= solution.steps
+                    && other.energy <= solution.energy
+                    && (other.steps, other.energy) != (solution.steps, solution.energy)
+            }));
+        }
+    }
+}
@@ -1,27 +1,43 @@
-use crate::components::{Activable, ActivationKind, Position, Renderable};
+use crate::components::{Activable, ActivationBehaviour, ActivationKind, Position};
 use crate::map;
-use bracket_lib::prelude::*;
+use crate::turn_history::{Action, TurnsHistory};
 use legion::prelude::*;
 
 pub fn activable_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("activable_system")
         .read_resource::<map::Map>()
-        .with_query(<(Write<Activable>, Write<Renderable>, Read<Position>)>::query())
-        .build(|_, mut world, map, query| {
-            for (mut activable, mut renderable, position) in query.iter_mut(&mut world) {
-                match activable.kind {
-                    ActivationKind::Weight => {
-                        activable.active = map.is_blocked(position.x, position.y);
+        .write_resource::<TurnsHistory>()
+        .with_query(<(Write<Activable>, Read<Position>)>::query())
+        .build(|_, mut world, (map, history), query| {
+            for (entity, (mut activable, position)) in query.iter_entities_mut(&mut world) {
+                let triggered = match activable.kind {
+                    ActivationKind::Weight => map.is_blocked(position.x, position.y),
+                    ActivationKind::Laser => map.is_lasered(position.x, position.y),
+                };
+                activable.active = match activable.behaviour {
+                    ActivationBehaviour::Momentary => triggered,
+                    ActivationBehaviour::Latching => {
+                        if triggered && !activable.latched {
+                            activable.latched = true;
+                            history.record_consequence(Action::Latches(entity));
+                        }
+                        activable.latched
                     }
-                    ActivationKind::Laser => {
-                        activable.active = map.is_lasered(position.x, position.y);
+                    ActivationBehaviour::Timed(turns) => {
+                        let step = history.steps;
+                        if triggered && activable.last_triggered != Some(step) {
+                            history.record_consequence(Action::Triggers(
+                                entity,
+                                activable.last_triggered,
+                                step,
+                            ));
+                            activable.last_triggered = Some(step);
+                        }
+                        activable
+                            .last_triggered
+                            .map_or(false, |last| step - last <= turns)
                     }
                 };
-                renderable.fg = if activable.active {
-                    RGB::named(GREEN)
-                } else {
-                    RGB::named(RED)
-                };
             }
         })
 }
@@ -1,7 +1,5 @@
-use crate::components::{Activable, Door, Renderable};
-use crate::glyphs::*;
+use crate::components::{Activable, Door};
 use crate::map;
-use bracket_lib::prelude::*;
 use legion::prelude::*;
 
 pub fn door_system() -> Box<dyn Schedulable> {
@@ -10,30 +8,15 @@ pub fn door_system() -> Box<dyn Schedulable> {
         .read_component::<Activable>()
         .with_query(<(Read<Door>,)>::query())
         .with_query(<(Write<Door>,)>::query())
-        .build(|cmd, mut world, _, (query1, query2)| {
+        .build(|_, mut world, _, (query1, query2)| {
             let mut opened = vec![];
             for (door,) in query1.iter(&world) {
-                let mut open = true;
-                for activable in door.activations.iter() {
-                    open = open && world.get_component::<Activable>(*activable).unwrap().active;
-                }
-                opened.push(open);
+                opened.push(door.logic.eval(&|activable: &Entity| {
+                    world.get_component::<Activable>(*activable).unwrap().active
+                }));
             }
-            for (idx, (entity, (mut door,))) in query2.iter_entities_mut(&mut world).enumerate() {
+            for (idx, (mut door,)) in query2.iter_mut(&mut world).enumerate() {
                 door.opened = opened[idx];
-                if door.opened {
-                    cmd.remove_component::<Renderable>(entity);
-                } else {
-                    cmd.add_component::<Renderable>(
-                        entity,
-                        Renderable {
-                            glyph: DOOR_H_CLOSED,
-                            fg: RGB::named(RED),
-                            bg: RGB::named(BLACK),
-                            render_order: 1,
-                        },
-                    );
-                }
             }
         })
 }
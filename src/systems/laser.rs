@@ -1,46 +1,39 @@
 use crate::components::{
-    Actuated, Actuator, Cardinal, Laser, Position, ReflectsLaser, Renderable, UndoActuated,
+    Actuated, Actuator, Cardinal, Laser, Position, ReflectsLaser, UndoActuated,
 };
-use crate::{glyphs::*, map};
+use crate::map;
 use legion::prelude::*;
 
 pub fn reflector_actuation_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("reflector_acturation_system")
         .read_component::<ReflectsLaser>()
+        .with_query(<(Write<ReflectsLaser>, Write<Actuator>)>::query().filter(tag::<Actuated>()))
         .with_query(
-            <(Write<ReflectsLaser>, Write<Renderable>, Write<Actuator>)>::query()
-                .filter(tag::<Actuated>()),
-        )
-        .with_query(
-            <(Write<ReflectsLaser>, Write<Renderable>)>::query().filter(tag::<UndoActuated>()),
+            <(Write<ReflectsLaser>, Write<Actuator>)>::query().filter(tag::<UndoActuated>()),
         )
         .build(|cmd, mut world, _, (query1, query2)| {
-            for (entity, (mut reflector, mut renderable, mut actuator)) in
-                query1.iter_entities_mut(&mut world)
-            {
+            for (entity, (mut reflector, mut actuator)) in query1.iter_entities_mut(&mut world) {
                 match reflector.orientation {
                     Cardinal::NE => {
                         reflector.orientation = Cardinal::NW;
                         actuator.state = 0;
-                        renderable.glyph = REFLECTOR_NW;
                     }
                     _ => {
                         reflector.orientation = Cardinal::NE;
                         actuator.state = 1;
-                        renderable.glyph = REFLECTOR_NE;
                     }
                 };
                 cmd.remove_tag::<Actuated>(entity);
             }
-            for (entity, (mut reflector, mut renderable)) in query2.iter_entities_mut(&mut world) {
+            for (entity, (mut reflector, mut actuator)) in query2.iter_entities_mut(&mut world) {
                 match reflector.orientation {
                     Cardinal::NE => {
                         reflector.orientation = Cardinal::NW;
-                        renderable.glyph = REFLECTOR_NW;
+                        actuator.state = 0;
                     }
                     _ => {
                         reflector.orientation = Cardinal::NE;
-                        renderable.glyph = REFLECTOR_NE;
+                        actuator.state = 1;
                     }
                 };
                 cmd.remove_tag::<UndoActuated>(entity);
@@ -67,23 +60,7 @@ pub fn laser_system() -> Box<dyn Schedulable> {
                     if map.is_blocking_laser(cur_x, cur_y, world) {
                         break;
                     } else if let Some(orientation) = map.is_reflecting_laser(cur_x, cur_y, world) {
-                        direction = match orientation {
-                            Cardinal::NE => match direction {
-                                Cardinal::N => Cardinal::E,
-                                Cardinal::S => Cardinal::W,
-                                Cardinal::E => Cardinal::N,
-                                Cardinal::W => Cardinal::S,
-                                _ => Cardinal::N,
-                            },
-                            Cardinal::NW => match direction {
-                                Cardinal::N => Cardinal::W,
-                                Cardinal::S => Cardinal::E,
-                                Cardinal::W => Cardinal::N,
-                                Cardinal::E => Cardinal::S,
-                                _ => Cardinal::N,
-                            },
-                            _ => Cardinal::N,
-                        }
+                        direction = direction.reflect(orientation);
                     }
                 }
             }
@@ -18,3 +18,11 @@ pub fn build_systems() -> Schedule {
         .add_system(level_end::level_end_system())
         .build()
 }
+
+/// Only index the map and trace the lasers, leaving the level state untouched.
+pub fn build_laser_systems() -> Schedule {
+    Schedule::builder()
+        .add_system(map_indexing::map_indexing_system())
+        .add_system(laser::laser_system())
+        .build()
+}
@@ -1,5 +1,5 @@
-use crate::components::{Actuated, Player, Position, Renderable, UndoActuated};
-use bracket_lib::prelude::*;
+use crate::components::{Activable, Actuated, Position, UndoActuated};
+use crate::player::PlayerInput;
 use legion::prelude::*;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -7,8 +7,10 @@ pub enum Action {
     Moves(Entity, (i32, i32), (i32, i32)), // Entity moved from x,y to x,y
     Actuates(Entity),                      // Entity has been actuated
     UseEnergy(i32),
+    Latches(Entity),                    // Latching activable has been triggered
+    Triggers(Entity, Option<i32>, i32), // Timed activable last triggered step changed from to
 }
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TurnState {
     Running,
     PlayerDead,
@@ -20,6 +22,10 @@ pub struct TurnsHistory {
     pub energy_used: i32,
     pub state: TurnState,
     pub history: Vec<Vec<Action>>,
+    /// Input played at each turn of history, None when the turn is not a player input
+    pub inputs: Vec<Option<PlayerInput>>,
+    /// Undone turns, the last one is redone first
+    pub redo: Vec<(Option<PlayerInput>, Vec<Action>)>,
 }
 impl TurnsHistory {
     pub fn new() -> TurnsHistory {
@@ -28,9 +34,22 @@ impl TurnsHistory {
             energy_used: 0,
             state: TurnState::Running,
             history: vec![],
+            inputs: vec![],
+            redo: vec![],
+        }
+    }
+    /// Play a new turn, the undone turns can no longer be redone.
+    pub fn play_turn(&mut self, ecs: &mut World, input: Option<PlayerInput>, actions: Vec<Action>) {
+        self.redo.clear();
+        self.apply_turn(ecs, input, actions);
+    }
+    /// Play again the last undone turn, with the consequences it had.
+    pub fn redo_turn(&mut self, ecs: &mut World) {
+        if let Some((input, actions)) = self.redo.pop() {
+            self.apply_turn(ecs, input, actions);
         }
     }
-    pub fn play_turn(&mut self, ecs: &mut World, actions: Vec<Action>) {
+    fn apply_turn(&mut self, ecs: &mut World, input: Option<PlayerInput>, actions: Vec<Action>) {
         for &action in actions.iter() {
             match action {
                 Action::Moves(entity, (_x1, _y1), (x2, y2)) => {
@@ -44,9 +63,18 @@ impl TurnsHistory {
                 Action::UseEnergy(x) => {
                     self.energy_used += x;
                 }
+                Action::Latches(entity) => {
+                    ecs.get_component_mut::<Activable>(entity).unwrap().latched = true;
+                }
+                Action::Triggers(entity, _from, to) => {
+                    ecs.get_component_mut::<Activable>(entity)
+                        .unwrap()
+                        .last_triggered = Some(to);
+                }
             }
         }
         self.history.push(actions);
+        self.inputs.push(input);
         self.steps += 1;
     }
     pub fn undo_last_turn(&mut self, ecs: &mut World) {
@@ -64,18 +92,29 @@ impl TurnsHistory {
                     Action::UseEnergy(x) => {
                         self.energy_used -= x;
                     }
+                    Action::Latches(entity) => {
+                        ecs.get_component_mut::<Activable>(entity).unwrap().latched = false;
+                    }
+                    Action::Triggers(entity, from, _to) => {
+                        ecs.get_component_mut::<Activable>(entity)
+                            .unwrap()
+                            .last_triggered = from;
+                    }
                 }
             }
-            if self.state == TurnState::PlayerDead {
-                let query = <(Write<Renderable>,)>::query().filter(tag::<Player>());
-                for (mut renderable,) in query.iter_mut(ecs) {
-                    renderable.fg = RGB::named(YELLOW);
-                }
-            }
+            let input = self.inputs.pop().unwrap();
+            self.redo.push((input, actions));
             self.steps -= 1;
             self.state = TurnState::Running;
         }
     }
+    /// Add an action that is a consequence of the last turn, so that it is undone with it.
+    /// Actions happening before the first turn are part of the level initial state.
+    pub fn record_consequence(&mut self, action: Action) {
+        if let Some(actions) = self.history.last_mut() {
+            actions.push(action);
+        }
+    }
     pub fn undo(&mut self, n_steps: i32, ecs: &mut World) {
         for _i in 0..n_steps {
             self.undo_last_turn(ecs);